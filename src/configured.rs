@@ -0,0 +1,190 @@
+use crate::ChainMap;
+use std::{borrow::Borrow, collections::HashMap, hash::Hash};
+
+/// What a `ChainMapConfigured` does when an insert would shadow a key
+/// defined in a parent layer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShadowPolicy {
+    /// Shadowing is allowed silently, as with a plain `ChainMap`.
+    Allow,
+    /// Shadowing is allowed, but the callback set with
+    /// `ChainMapConfigured::on_shadow` is called with the key first.
+    Warn,
+    /// Shadowing is rejected and the insert returns an error.
+    Deny,
+}
+
+/// The callback invoked under `ShadowPolicy::Warn`.
+type ShadowCallback<K> = Box<dyn FnMut(&K)>;
+
+/// A `ChainMap` that enforces a `ShadowPolicy` on every insert.
+///
+/// An insert shadows a key when the key is not yet in the top layer but is
+/// defined in one of its parents. Overwriting a key already in the top
+/// layer never counts as shadowing.
+pub struct ChainMapConfigured<K, V> {
+    chain_map: ChainMap<K, V>,
+    policy: ShadowPolicy,
+    on_shadow: Option<ShadowCallback<K>>,
+}
+
+impl<K: Hash + Eq, V> ChainMapConfigured<K, V> {
+    /// Wraps `chain_map` so that inserts follow `policy`.
+    pub fn new(chain_map: ChainMap<K, V>, policy: ShadowPolicy) -> Self {
+        Self {
+            chain_map,
+            policy,
+            on_shadow: None,
+        }
+    }
+
+    /// Sets the callback used under `ShadowPolicy::Warn`. Without one, `Warn`
+    /// behaves like `Allow`.
+    pub fn on_shadow<F>(mut self, f: F) -> Self
+    where
+        F: FnMut(&K) + 'static,
+    {
+        self.on_shadow = Some(Box::new(f));
+        self
+    }
+
+    /// Returns the policy inserts are checked against.
+    pub fn policy(&self) -> ShadowPolicy {
+        self.policy
+    }
+
+    /// Inserts a key-value pair into the top layer, as `ChainMap::insert`
+    /// does, after checking it against the shadowing policy.
+    ///
+    /// Under `ShadowPolicy::Deny`, an insert that would shadow a parent
+    /// layer's key is rejected and the pair is handed back as the error.
+    pub fn insert(&mut self, key: K, value: V) -> Result<Option<V>, (K, V)> {
+        if self.chain_map.is_inherited(&key) {
+            match self.policy {
+                ShadowPolicy::Allow => {}
+                ShadowPolicy::Warn => {
+                    if let Some(f) = &mut self.on_shadow {
+                        f(&key);
+                    }
+                }
+                ShadowPolicy::Deny => return Err((key, value)),
+            }
+        }
+        Ok(self.chain_map.insert(key, value))
+    }
+
+    /// Returns the value corresponding to the supplied key.
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.chain_map.get(key)
+    }
+
+    /// Returns true if any layer of the chain contains the key.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.chain_map.contains_key(key)
+    }
+
+    /// Removes `key` from the top layer, returning its value. Parent layers
+    /// are left untouched, so a parent's value for `key` becomes visible
+    /// again.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.chain_map.top_layer_mut().remove(key)
+    }
+
+    /// An iterator visiting all visible key-value pairs, as
+    /// `ChainMap::iter` does.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.chain_map.iter()
+    }
+
+    /// Pushes a new, empty, layer onto the chain.
+    pub fn new_child(&mut self) {
+        self.chain_map.new_child();
+    }
+
+    /// Removes the top layer, as `ChainMap::remove_child` does.
+    pub fn remove_child(&mut self) -> Option<HashMap<K, V>> {
+        self.chain_map.remove_child()
+    }
+
+    /// Returns the wrapped chain.
+    pub fn chain_map(&self) -> &ChainMap<K, V> {
+        &self.chain_map
+    }
+
+    /// Consumes the wrapper, returning the wrapped chain.
+    pub fn into_inner(self) -> ChainMap<K, V> {
+        self.chain_map
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{ChainMap, ShadowPolicy};
+    use std::{cell::RefCell, rc::Rc};
+
+    #[test]
+    fn deny() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        let mut configured = chain_map.with_shadow_policy(ShadowPolicy::Deny);
+        assert_eq!(configured.insert("x", 1), Err(("x", 1)));
+        assert_eq!(configured.insert("y", 1), Ok(None));
+        assert_eq!(configured.insert("y", 2), Ok(Some(1)));
+        assert_eq!(configured.get("x"), Some(&0));
+    }
+
+    #[test]
+    fn warn() {
+        let warned = Rc::new(RefCell::new(Vec::new()));
+        let log = Rc::clone(&warned);
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        let mut configured = chain_map
+            .with_shadow_policy(ShadowPolicy::Warn)
+            .on_shadow(move |k: &&str| log.borrow_mut().push(*k));
+        configured.new_child();
+        assert_eq!(configured.insert("x", 1), Ok(None));
+        assert_eq!(configured.insert("x", 2), Ok(Some(1)));
+        assert_eq!(configured.insert("y", 2), Ok(None));
+        assert_eq!(configured.into_inner().get("x"), Some(&2));
+        assert_eq!(*warned.borrow(), vec!["x"]);
+    }
+
+    #[test]
+    fn allow() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        let mut configured = chain_map.with_shadow_policy(ShadowPolicy::Allow);
+        assert_eq!(configured.policy(), ShadowPolicy::Allow);
+        assert_eq!(configured.insert("x", 1), Ok(None));
+        assert_eq!(configured.remove_child().unwrap().get("x"), Some(&1));
+        assert_eq!(configured.chain_map().get("x"), Some(&0));
+    }
+
+    #[test]
+    fn remove() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        let mut configured = chain_map.with_shadow_policy(ShadowPolicy::Allow);
+        assert_eq!(configured.insert("x", 1), Ok(None));
+        assert_eq!(configured.remove("x"), Some(1));
+        assert_eq!(configured.remove("x"), None);
+        assert!(configured.contains_key("x"));
+        assert_eq!(configured.iter().collect::<Vec<_>>(), vec![(&"x", &0)]);
+    }
+}
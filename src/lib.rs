@@ -1,5 +1,32 @@
+//! Tiered maps and sets for representing lexically scoped variables.
+//!
+//! A [`ChainMap`] is a stack of `HashMap` layers, the last of which is the
+//! current scope. Lookups start at the top layer and fall back to each
+//! enclosing layer in turn, so an inner binding shadows an outer one with
+//! the same key. [`ChainSet`] does the same for `HashSet`s.
+//!
+//! A chain always has at least one layer, the base. Removing the last
+//! child leaves an empty base behind rather than an empty chain.
+mod configured;
 mod map;
+mod map_ref;
 mod set;
 
-pub use map::ChainMap;
+pub use configured::{ChainMapConfigured, ShadowPolicy};
+pub use map::{ChainMap, ComparisonResult, DuplicateKeyError, InvariantViolation, Snapshot};
+pub use map_ref::ChainMapRef;
 pub use set::ChainSet;
+
+/// Asserts that a [`ChainMap`] is consistent, as reported by
+/// `ChainMap::is_consistent`. Like `debug_assert!`, this is only checked in
+/// debug builds.
+#[macro_export]
+macro_rules! debug_assert_consistent {
+    ($chain_map:expr) => {
+        if cfg!(debug_assertions) {
+            if let Err(e) = (&$chain_map).check_invariants() {
+                panic!("chain map is not consistent: {}", e);
+            }
+        }
+    };
+}
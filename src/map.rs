@@ -1,5 +1,23 @@
-use std::{borrow::Borrow, collections::HashMap, hash::Hash, mem::replace, ops::Index};
+use crate::{ChainMapConfigured, ChainMapRef, ShadowPolicy};
+use std::{
+    borrow::Borrow,
+    cmp::Ordering,
+    collections::{hash_map::Entry, BTreeMap, HashMap, HashSet},
+    error::Error,
+    fmt::{self, Debug, Display},
+    hash::{BuildHasher, Hash},
+    io::{self, Write},
+    iter::{FromIterator, Sum},
+    marker::PhantomData,
+    mem::{replace, size_of, take},
+    ops::{Index, IndexMut},
+};
 
+/// A stack of `HashMap`s where lookups search from the innermost (top)
+/// layer out to the base layer.
+///
+/// A `ChainMap` always contains at least one layer, and no method will
+/// leave it with zero.
 pub struct ChainMap<K, V> {
     pub(crate) maps: Vec<HashMap<K, V>>,
 }
@@ -8,6 +26,69 @@ impl<K: Hash + Eq, V> ChainMap<K, V> {
     pub fn new(map: HashMap<K, V>) -> Self {
         Self { maps: vec![map] }
     }
+    /// Creates a chain from a list of layers, the first being the base and
+    /// the last the top.
+    ///
+    /// Returns `None` if `maps` is empty, since a chain must have at least
+    /// one layer.
+    pub fn new_with_maps(maps: Vec<HashMap<K, V>>) -> Option<Self> {
+        if maps.is_empty() {
+            return None;
+        }
+        Some(Self { maps })
+    }
+
+    /// Creates a chain with `depth` layers, where the layer at index `i` is
+    /// produced by calling `f(i)`. `f` is called once for each layer,
+    /// starting with the base.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `depth` is 0, since a chain must have at least one layer.
+    pub fn from_fn<F>(depth: usize, f: F) -> Self
+    where
+        F: FnMut(usize) -> HashMap<K, V>,
+    {
+        assert!(depth > 0, "a chain map must have at least one layer");
+        Self {
+            maps: (0..depth).map(f).collect(),
+        }
+    }
+
+    /// Creates a chain from an iterator of layers, each given as an
+    /// iterator of entries. The first layer is the base and the last the
+    /// top.
+    ///
+    /// If `nested` is empty, the chain has a single empty base layer, as
+    /// with `ChainMap::default`.
+    pub fn chain_from_nested<I, I2>(nested: I) -> Self
+    where
+        I: IntoIterator<Item = I2>,
+        I2: IntoIterator<Item = (K, V)>,
+    {
+        let maps: Vec<HashMap<K, V>> = nested
+            .into_iter()
+            .map(|layer| layer.into_iter().collect())
+            .collect();
+        Self::new_with_maps(maps).unwrap_or_default()
+    }
+
+    /// Creates a chain from the entries of its base layer followed by the
+    /// entries of each child layer, with the last child on top.
+    pub fn from_iter_of_layers<I>(base: I, children: Vec<I>) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let mut maps = Vec::with_capacity(1 + children.len());
+        maps.push(base.into_iter().collect());
+        maps.extend(
+            children
+                .into_iter()
+                .map(|layer| layer.into_iter().collect()),
+        );
+        Self { maps }
+    }
+
     /// Inserts a key-value pair into the map.
     /// If the map did not have this key present, None is returned.
     pub fn insert(&mut self, key: K, value: V) -> Option<V> {
@@ -19,27 +100,22 @@ impl<K: Hash + Eq, V> ChainMap<K, V> {
     /// The supplied key may be any borrowed form of the map's key type, but
     /// `Hash` and `Eq` on the borrowed form *must* match those for
     /// the key type.
-    pub fn get<Q: ?Sized>(&self, key: &Q) -> Option<&V>
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
     where
         K: Borrow<Q>,
-        Q: Hash + Eq,
+        Q: Hash + Eq + ?Sized,
     {
-        for map in self.maps.iter().rev() {
-            if let Some(v) = map.get(key) {
-                return Some(v);
-            }
-        }
-        None
+        self.view().get(key)
     }
     /// Returns a mutable reference to the value corresponding to the key.
     ///
     /// The supplied key may be any borrowed form of the map's key type, but
     /// `Hash` and `Eq` on the borrowed form *must* match those for
     /// the key type.
-    pub fn get_mut<Q: ?Sized>(&mut self, key: &Q) -> Option<&mut V>
+    pub fn get_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
     where
         K: Borrow<Q>,
-        Q: Hash + Eq,
+        Q: Hash + Eq + ?Sized,
     {
         for map in self.maps.iter_mut().rev() {
             if let Some(v) = map.get_mut(key) {
@@ -57,157 +133,4791 @@ impl<K: Hash + Eq, V> ChainMap<K, V> {
         self.maps.push(map);
     }
 
+    /// Consumes the chain, returning it with a new empty layer on top.
+    pub fn into_child(mut self) -> Self {
+        self.new_child();
+        self
+    }
+
+    /// Consumes the chain, returning it with `key` and `value` inserted
+    /// into the top layer.
+    pub fn with_insert(mut self, key: K, value: V) -> Self {
+        self.insert(key, value);
+        self
+    }
+
+    /// Removes and returns the top layer.
+    ///
+    /// If only the base layer remains, its entries are taken and it is
+    /// replaced with an empty layer, so the chain is never left without
+    /// a layer.
     pub fn remove_child(&mut self) -> Option<HashMap<K, V>> {
         if self.maps.len() == 1 {
-            let ret = replace(&mut self.maps[0], HashMap::new());
+            let ret = take(&mut self.maps[0]);
             Some(ret)
         } else {
             self.maps.pop()
         }
     }
-}
 
-impl<K: Hash + Eq, V> Default for ChainMap<K, V> {
-    fn default() -> Self {
-        Self {
-            maps: vec![HashMap::new()],
+    /// Returns the number of visible keys in the chain.
+    ///
+    /// A key that is shadowed by a child layer is only counted once. See
+    /// `chain_len` for the total number of entries stored.
+    pub fn len(&self) -> usize {
+        self.view().len()
+    }
+
+    /// Returns true if no layer of the chain contains any entries.
+    pub fn is_empty(&self) -> bool {
+        self.view().is_empty()
+    }
+
+    /// An alias for `len`.
+    pub fn count(&self) -> usize {
+        self.view().count()
+    }
+
+    /// Returns the number of visible entries for which `f` returns true.
+    pub fn count_if<F>(&self, f: F) -> usize
+    where
+        F: Fn(&K, &V) -> bool,
+    {
+        self.view().count_if(f)
+    }
+
+    /// An iterator visiting all visible key-value pairs, starting with
+    /// the innermost layer. Shadowed entries are skipped, so each key
+    /// is yielded exactly once.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.view().iter()
+    }
+
+    /// Returns the visible entry with the minimum value produced by `f`.
+    ///
+    /// If several entries are equally minimum, which one is returned is
+    /// unspecified.
+    pub fn min_by_key<B, F>(&self, f: F) -> Option<(&K, &V)>
+    where
+        B: Ord,
+        F: Fn(&V) -> B,
+    {
+        self.iter().min_by_key(|(_, v)| f(v))
+    }
+
+    /// Returns the visible entry with the maximum value produced by `f`.
+    ///
+    /// If several entries are equally maximum, which one is returned is
+    /// unspecified.
+    pub fn max_by_key<B, F>(&self, f: F) -> Option<(&K, &V)>
+    where
+        B: Ord,
+        F: Fn(&V) -> B,
+    {
+        self.iter().max_by_key(|(_, v)| f(v))
+    }
+
+    /// Returns the visible entry whose value is the minimum with respect
+    /// to the comparison function `f`.
+    pub fn min_by<F>(&self, f: F) -> Option<(&K, &V)>
+    where
+        F: Fn(&V, &V) -> Ordering,
+    {
+        self.iter().min_by(|(_, a), (_, b)| f(a, b))
+    }
+
+    /// Returns the visible entry whose value is the maximum with respect
+    /// to the comparison function `f`.
+    pub fn max_by<F>(&self, f: F) -> Option<(&K, &V)>
+    where
+        F: Fn(&V, &V) -> Ordering,
+    {
+        self.iter().max_by(|(_, a), (_, b)| f(a, b))
+    }
+
+    /// Creates a single layer chain with the visible keys and values of
+    /// this chain swapped.
+    ///
+    /// When more than one visible key maps to the same value, the key
+    /// from the innermost layer is kept. If those keys live in the same
+    /// layer, which one is kept is unspecified.
+    pub fn invert(&self) -> ChainMap<V, K>
+    where
+        K: Clone,
+        V: Hash + Eq + Clone,
+    {
+        let mut map = HashMap::new();
+        for (k, v) in self.iter() {
+            map.entry(v.clone()).or_insert_with(|| k.clone());
         }
+        ChainMap::new(map)
     }
-}
 
-impl<K, Q: ?Sized, V> Index<&Q> for ChainMap<K, V>
-where
-    K: Eq + Hash + Borrow<Q>,
-    Q: Eq + Hash,
-{
-    type Output = V;
+    /// Returns true if any layer of the chain contains the key.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.view().contains_key(key)
+    }
 
-    /// Returns a reference to the value corresponding to the supplied key.
+    /// Returns the number of layers in the chain. This is always at
+    /// least 1.
+    pub fn depth(&self) -> usize {
+        self.view().depth()
+    }
+
+    /// Borrows every layer of the chain as a `ChainMapRef`.
+    pub fn view(&self) -> ChainMapRef<'_, K, V> {
+        ChainMapRef { maps: &self.maps }
+    }
+
+    /// Borrows the layers `from..=to` as a `ChainMapRef`, where layer 0
+    /// is the base.
     ///
     /// # Panics
     ///
-    /// Panics if the key is not present in the `HashMap`.
-    #[inline]
-    fn index(&self, key: &Q) -> &V {
-        self.get(key).expect("no entry found for key")
+    /// Panics if `from > to` or `to` is out of bounds.
+    pub fn sub_chain(&self, from: usize, to: usize) -> ChainMapRef<'_, K, V> {
+        ChainMapRef {
+            maps: self.layer_range(from, to),
+        }
     }
-}
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use std::default::Default;
+    /// Shrinks the capacity of every layer with a lower limit. Each layer
+    /// will keep a capacity of at least its length and `min_capacity`.
+    pub fn shrink_to(&mut self, min_capacity: usize) {
+        for map in &mut self.maps {
+            map.shrink_to(min_capacity);
+        }
+    }
 
-    #[test]
-    fn initialization() {
-        let mut test_map = HashMap::new();
-        test_map.insert("test", 1);
-        let chain_map = ChainMap::new(test_map);
+    /// Shrinks the capacity of every layer as much as possible.
+    pub fn shrink_to_fit(&mut self) {
+        self.shrink_to(0);
+    }
 
-        assert!(chain_map.maps.len() > 0);
-        assert_eq!(chain_map.maps[0].get("test"), Some(&1));
+    /// Returns the capacity of each layer, starting with the base.
+    pub fn layer_capacities(&self) -> Vec<usize> {
+        self.maps.iter().map(HashMap::capacity).collect()
     }
 
-    #[test]
-    fn initialization_default() {
-        let chain_map: ChainMap<(), ()> = ChainMap::default();
+    /// Returns the number of entries in each layer, starting with the
+    /// base. Shadowed entries are counted in every layer they appear in.
+    pub fn layer_lengths(&self) -> Vec<usize> {
+        self.maps.iter().map(HashMap::len).collect()
+    }
 
-        assert!(chain_map.maps.len() > 0);
-        assert!(chain_map.maps[0].is_empty());
+    /// Removes every entry from every layer and returns the visible
+    /// key-value pairs. Shadowed entries are dropped, so each key is
+    /// yielded exactly once with its innermost value.
+    ///
+    /// The layers themselves are kept, so the chain has the same depth
+    /// afterwards.
+    pub fn drain(&mut self) -> impl Iterator<Item = (K, V)> {
+        let mut ret = HashMap::new();
+        for map in &mut self.maps {
+            ret.extend(map.drain());
+        }
+        ret.into_iter()
     }
 
-    #[test]
-    fn insert() {
-        let mut chain_map = ChainMap::default();
-        assert!(chain_map.insert("test", 1).is_none());
+    /// Consumes the chain, returning the visible key-value pairs.
+    /// Shadowed entries are dropped, so each key is yielded exactly once
+    /// with its innermost value.
+    pub fn into_drain(self) -> impl Iterator<Item = (K, V)> {
+        let mut ret = HashMap::new();
+        for map in self.maps {
+            ret.extend(map);
+        }
+        ret.into_iter()
+    }
 
-        assert_eq!(chain_map.maps[0].get("test"), Some(&1));
+    /// Splits the visible entries into two maps, the first holding every
+    /// entry for which `f` returns true and the second holding the rest.
+    pub fn partition<F>(&self, f: F) -> (HashMap<K, V>, HashMap<K, V>)
+    where
+        F: Fn(&K, &V) -> bool,
+        K: Clone,
+        V: Clone,
+    {
+        let mut matched = HashMap::new();
+        let mut rest = HashMap::new();
+        for (k, v) in self.iter() {
+            if f(k, v) {
+                matched.insert(k.clone(), v.clone());
+            } else {
+                rest.insert(k.clone(), v.clone());
+            }
+        }
+        (matched, rest)
     }
 
-    #[test]
-    fn get() {
-        let mut chain_map = ChainMap::default();
-        chain_map.insert("test", 1);
+    /// Combines the visible entries of two chains into a new single layer
+    /// chain. Only keys visible in both chains are kept, with their value
+    /// produced by calling `f` on the value from each chain.
+    pub fn zip_with<W, X, F>(&self, other: &ChainMap<K, W>, f: F) -> ChainMap<K, X>
+    where
+        K: Clone,
+        F: Fn(&V, &W) -> X,
+    {
+        let map = self
+            .iter()
+            .filter_map(|(k, v)| other.get(k).map(|w| (k.clone(), f(v, w))))
+            .collect();
+        ChainMap::new(map)
+    }
 
-        assert_eq!(chain_map.get(&"test"), Some(&1));
+    /// Inserts a key-value pair into the top layer only if the key is not
+    /// present in any layer of the chain.
+    ///
+    /// If the key already exists, the chain is left unchanged and the
+    /// key-value pair is handed back in the `Err`.
+    pub fn insert_unique(&mut self, key: K, value: V) -> Result<(), (K, V)> {
+        if self.contains_key(&key) {
+            return Err((key, value));
+        }
+        self.insert(key, value);
+        Ok(())
     }
 
-    #[test]
-    fn get_none() {
-        let chain_map: ChainMap<&str, ()> = ChainMap::default();
-        assert_eq!(chain_map.get(&"test"), None);
+    /// Moves the innermost binding for `key` out of its parent layer and
+    /// into the top layer.
+    ///
+    /// Returns false, leaving the chain unchanged, if the key is already
+    /// in the top layer or is not in any layer.
+    pub fn move_to_top<Q>(&mut self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let (top, parents) = match self.maps.split_last_mut() {
+            Some(split) => split,
+            None => return false,
+        };
+        if top.contains_key(key) {
+            return false;
+        }
+        match parents
+            .iter_mut()
+            .rev()
+            .find_map(|map| map.remove_entry(key))
+        {
+            Some((k, v)) => {
+                top.insert(k, v);
+                true
+            }
+            None => false,
+        }
     }
 
-    #[test]
-    fn get_mut() {
-        let mut chain_map = ChainMap::default();
-        chain_map.insert("test", 1);
+    /// An iterator visiting every entry in every layer, including shadowed
+    /// ones, along with the index of the layer it is stored in. Layers are
+    /// visited from the base (index 0) to the top.
+    pub fn iter_with_depth(&self) -> impl Iterator<Item = (usize, &K, &V)> {
+        self.view().iter_with_depth()
+    }
 
-        let test_value = chain_map.get_mut(&"test");
-        assert_eq!(test_value, Some(&mut 1));
-        *test_value.unwrap() += 1;
-        let changed = chain_map.get(&"test");
-        assert_eq!(changed, Some(&2));
+    /// Returns the first visible entry for which `f` returns true,
+    /// searching in the same order as `iter`.
+    pub fn find<F>(&self, f: F) -> Option<(&K, &V)>
+    where
+        F: Fn(&K, &V) -> bool,
+    {
+        self.view().find(f)
     }
 
-    #[test]
-    fn get_mut_outer() {
-        let mut chain_map = ChainMap::default();
-        chain_map.insert("outer", 1);
-        chain_map.new_child();
-        chain_map.insert("inner", 2);
-        let ret = chain_map.get_mut("outer").unwrap();
-        *ret += 9000;
+    /// Returns the first visible entry for which `f` returns true,
+    /// searching the layers from the base to the top.
+    pub fn rfind<F>(&self, f: F) -> Option<(&K, &V)>
+    where
+        F: Fn(&K, &V) -> bool,
+    {
+        self.view().rfind(f)
+    }
 
-        let changed = chain_map.get(&"outer");
-        assert_eq!(changed, Some(&9001));
+    /// Sorts the layers with the comparator `f`, the layer that sorts last
+    /// becoming the new top.
+    ///
+    /// This changes which layer shadows which, so lookups may return
+    /// different values afterwards. The number of layers is unchanged.
+    pub fn sort_layers_by<F>(&mut self, f: F)
+    where
+        F: FnMut(&HashMap<K, V>, &HashMap<K, V>) -> Ordering,
+    {
+        self.maps.sort_by(f);
     }
 
-    #[test]
-    fn index() {
-        let mut chain_map = ChainMap::default();
-        chain_map.insert("test", 1);
+    /// Merges the layers `from..=to` into a single map, with entries from
+    /// inner layers replacing those from outer layers.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `from > to` or `to` is out of bounds.
+    pub fn flatten_range(&self, from: usize, to: usize) -> HashMap<K, V>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        let mut ret = HashMap::new();
+        for map in self.layer_range(from, to) {
+            ret.extend(map.iter().map(|(k, v)| (k.clone(), v.clone())));
+        }
+        ret
+    }
 
-        assert_eq!(chain_map[&"test"], 1);
+    /// Collects the visible entries into a map of references into the
+    /// chain, without cloning any keys or values.
+    pub fn collect_visible(&self) -> HashMap<&K, &V> {
+        self.view().collect_visible()
     }
 
-    #[test]
-    fn new_child() {
-        let mut chain_map = ChainMap::default();
-        chain_map.insert("test", 1);
-        chain_map.new_child();
-        assert!(chain_map.maps.len() > 1);
+    /// An alias for `layer_lengths`.
+    pub fn layer_sizes(&self) -> Vec<usize> {
+        self.layer_lengths()
     }
 
-    #[test]
-    fn scopes() {
-        let mut chain_map = ChainMap::default();
-        chain_map.insert("x", 0);
-        chain_map.insert("y", 2);
-        chain_map.new_child();
-        chain_map.insert("x", 1);
-        assert_eq!(chain_map.get("x"), Some(&1));
-        assert_eq!(chain_map.get("y"), Some(&2));
+    /// Returns every key in the chain mapped to the number of layers that
+    /// contain it. A count greater than 1 means the key is shadowed.
+    pub fn histogram(&self) -> BTreeMap<K, usize>
+    where
+        K: Clone + Ord,
+    {
+        let mut ret = BTreeMap::new();
+        for map in &self.maps {
+            for k in map.keys() {
+                *ret.entry(k.clone()).or_insert(0) += 1;
+            }
+        }
+        ret
     }
 
-    #[test]
-    fn remove_child() {
-        let mut chain_map = ChainMap::default();
-        chain_map.insert("x", 0);
-        chain_map.insert("y", 2);
-        chain_map.new_child();
-        chain_map.insert("x", 1);
-        let ret = chain_map.remove_child().unwrap();
-        assert_eq!(ret.get("x"), Some(&1));
-        assert_eq!(chain_map.get("x"), Some(&0));
+    /// Rotates the layers in place so the layer at index `k` becomes the
+    /// base. See `Vec::rotate_left`.
+    ///
+    /// This changes which layer shadows which, so lookups may return
+    /// different values afterwards.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `k` is greater than the number of layers.
+    pub fn rotate_left(&mut self, k: usize) {
+        self.maps.rotate_left(k);
     }
 
-    #[test]
-    fn remove_child_length_1() {
-        let mut chain_map = ChainMap::default();
-        chain_map.insert("x", 0);
-        let _ = chain_map.remove_child();
-        assert_eq!(chain_map.get("x"), None);
-        assert!(chain_map.maps.len() == 1);
+    /// Rotates the layers in place so the top `k` layers become the base
+    /// layers. See `Vec::rotate_right`.
+    ///
+    /// This changes which layer shadows which, so lookups may return
+    /// different values afterwards.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `k` is greater than the number of layers.
+    pub fn rotate_right(&mut self, k: usize) {
+        self.maps.rotate_right(k);
+    }
+
+    /// Removes and returns the layer at `index`, shifting every layer
+    /// above it down by one.
+    ///
+    /// Returns `None` if `index` is out of bounds or if the chain only has
+    /// one layer, since the chain can not be left without a layer.
+    pub fn remove_layer_at(&mut self, index: usize) -> Option<HashMap<K, V>> {
+        if index >= self.maps.len() || self.maps.len() == 1 {
+            return None;
+        }
+        Some(self.maps.remove(index))
+    }
+
+    /// Inserts `map` as a new layer at `index`, shifting every layer at or
+    /// above it up by one. An `index` of 0 inserts a new base and an index
+    /// equal to `depth()` inserts a new top.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index > depth()`.
+    pub fn insert_layer_at(&mut self, index: usize, map: HashMap<K, V>) {
+        self.maps.insert(index, map);
+    }
+
+    /// Replaces the layer at `index` with `map`, returning the old layer.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    pub fn replace_layer_at(&mut self, index: usize, map: HashMap<K, V>) -> Option<HashMap<K, V>> {
+        Some(replace(&mut self.maps[index], map))
+    }
+
+    /// Removes every shadowed entry, keeping only the innermost entry for
+    /// each key. The layers themselves are kept, so the chain has the
+    /// same depth and lookups return the same values afterwards.
+    pub fn dedup_keys(&mut self) {
+        for i in (0..self.maps.len()).rev() {
+            let (lower, above) = self.maps.split_at_mut(i + 1);
+            lower[i].retain(|k, _| !above.iter().any(|map| map.contains_key(k)));
+        }
+    }
+
+    /// An iterator visiting every key that appears in more than one layer.
+    /// Each key is yielded once.
+    pub fn shadowed_keys(&self) -> impl Iterator<Item = &K> {
+        self.view().shadowed_keys()
+    }
+
+    /// Returns true if no key appears in more than one layer.
+    pub fn is_shadow_free(&self) -> bool {
+        self.view().is_shadow_free()
+    }
+
+    /// Asserts that no key appears in more than one layer.
+    ///
+    /// # Panics
+    ///
+    /// Panics, listing the shadowed keys, if any key appears in more than
+    /// one layer.
+    pub fn assert_no_shadowing(&self)
+    where
+        K: Debug,
+    {
+        let shadowed: Vec<_> = self.shadowed_keys().collect();
+        assert!(shadowed.is_empty(), "shadowed keys found: {:?}", shadowed);
+    }
+
+    /// Consumes the chain, returning a new chain with `f` applied to every
+    /// key in every layer. The layer structure is kept.
+    ///
+    /// If `f` maps two keys in the same layer to the same new key, only
+    /// one of the values is kept and which one is unspecified.
+    pub fn map_keys<J, F>(self, f: F) -> ChainMap<J, V>
+    where
+        J: Hash + Eq,
+        F: Fn(K) -> J,
+    {
+        let maps = self
+            .maps
+            .into_iter()
+            .map(|map| map.into_iter().map(|(k, v)| (f(k), v)).collect())
+            .collect();
+        ChainMap { maps }
+    }
+
+    /// Calls `f` on every layer, starting with the base.
+    pub fn map_layers<F>(&mut self, f: F)
+    where
+        F: FnMut(&mut HashMap<K, V>),
+    {
+        self.maps.iter_mut().for_each(f);
+    }
+
+    /// Collects the visible keys into a set of references into the chain.
+    pub fn collect_keys(&self) -> HashSet<&K> {
+        self.view().collect_keys()
+    }
+
+    /// An iterator visiting all visible values, in the same order as
+    /// `iter`.
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.view().values()
+    }
+
+    /// An iterator visiting a clone of every visible value, in the same
+    /// order as `iter`.
+    pub fn values_cloned(&self) -> impl Iterator<Item = V> + '_
+    where
+        V: Clone,
+    {
+        self.values().cloned()
+    }
+
+    /// Returns true if the layers at `depth_a` and `depth_b` contain
+    /// exactly the same key-value pairs.
+    ///
+    /// # Panics
+    ///
+    /// Panics if either index is out of bounds.
+    pub fn layer_eq(&self, depth_a: usize, depth_b: usize) -> bool
+    where
+        V: PartialEq,
+    {
+        self.maps[depth_a] == self.maps[depth_b]
+    }
+
+    /// Calls `f` with the top layer, returning its result.
+    pub fn apply<F, R>(&self, f: F) -> R
+    where
+        F: FnOnce(&HashMap<K, V>) -> R,
+    {
+        f(self.top())
+    }
+
+    /// Calls `f` with a mutable reference to the top layer, returning its
+    /// result.
+    pub fn apply_mut<F, R>(&mut self, f: F) -> R
+    where
+        F: FnOnce(&mut HashMap<K, V>) -> R,
+    {
+        f(self.top_layer_mut())
+    }
+
+    /// Checks that the chain's internal state is consistent.
+    pub fn check_invariants(&self) -> Result<(), InvariantViolation> {
+        if self.maps.is_empty() {
+            return Err(InvariantViolation::EmptyLayerVec);
+        }
+        Ok(())
+    }
+
+    /// Panics if `check_invariants` fails. Does nothing in release builds.
+    pub fn assert_invariants(&self) {
+        #[cfg(debug_assertions)]
+        {
+            if let Err(e) = self.check_invariants() {
+                panic!("{}", e);
+            }
+        }
+    }
+
+    /// Borrows the layers from `from_depth` up to and including the top as
+    /// a `ChainMapRef`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `from_depth` is out of bounds.
+    pub fn subtree(&self, from_depth: usize) -> ChainMapRef<'_, K, V> {
+        assert!(
+            from_depth < self.maps.len(),
+            "depth {} out of bounds for chain of depth {}",
+            from_depth,
+            self.maps.len()
+        );
+        ChainMapRef {
+            maps: &self.maps[from_depth..],
+        }
+    }
+
+    /// Returns the innermost value for `key` for which `f` returns true,
+    /// skipping over any values in inner layers that do not match.
+    pub fn first_match<Q, F>(&self, key: &Q, f: F) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        F: Fn(&V) -> bool,
+    {
+        self.view().first_match(key, f)
+    }
+
+    /// Returns the number of visible keys for which `f` returns true.
+    pub fn count_keys_by<F>(&self, f: F) -> usize
+    where
+        F: Fn(&K) -> bool,
+    {
+        self.iter().filter(|(k, _)| f(k)).count()
+    }
+
+    /// Returns true if every layer of the chain contains the key.
+    pub fn all_layers_contain<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.view().all_layers_contain(key)
+    }
+
+    /// Returns true if no layer of the chain contains the key. This is the
+    /// same as `!self.contains_key(key)`.
+    pub fn no_layer_contains<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.view().no_layer_contains(key)
+    }
+
+    /// Returns true if exactly one layer of the chain contains the key.
+    pub fn exactly_one_layer_contains<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.view().exactly_one_layer_contains(key)
+    }
+
+    /// Copies the innermost value for `key` from a parent layer into the
+    /// top layer, so the current scope has its own copy to modify.
+    ///
+    /// Returns false, leaving the chain unchanged, if the key is already
+    /// in the top layer or is not in any layer.
+    pub fn copy_to_top<Q>(&mut self, key: &Q) -> bool
+    where
+        K: Borrow<Q> + Clone,
+        Q: Hash + Eq + ?Sized,
+        V: Clone,
+    {
+        let (top, parents) = match self.maps.split_last_mut() {
+            Some(split) => split,
+            None => return false,
+        };
+        if top.contains_key(key) {
+            return false;
+        }
+        match parents.iter().rev().find_map(|map| map.get_key_value(key)) {
+            Some((k, v)) => {
+                top.insert(k.clone(), v.clone());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns true if the top layer contains the key, ignoring all parent
+    /// layers.
+    pub fn contains_key_local<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.view().contains_key_local(key)
+    }
+
+    /// Inserts a key-value pair into the top layer if the top layer does
+    /// not already contain the key. Parent layers are not considered.
+    ///
+    /// Returns true if the pair was inserted.
+    pub fn set_if_absent(&mut self, key: K, value: V) -> bool {
+        match self.top_layer_mut().entry(key) {
+            Entry::Vacant(entry) => {
+                entry.insert(value);
+                true
+            }
+            Entry::Occupied(_) => false,
+        }
+    }
+
+    /// Returns true if both chains have the same visible keys with equal
+    /// visible values, regardless of how their layers are arranged.
+    pub fn visible_eq(&self, other: &ChainMap<K, V>) -> bool
+    where
+        V: PartialEq,
+    {
+        self.len() == other.len() && self.is_subset_of(other)
+    }
+
+    /// Returns true if every visible key in `self` is also visible in
+    /// `other` with an equal value.
+    pub fn is_subset_of(&self, other: &ChainMap<K, V>) -> bool
+    where
+        V: PartialEq,
+    {
+        self.iter().all(|(k, v)| other.get(k) == Some(v))
+    }
+
+    /// Returns true if every visible key in `other` is also visible in
+    /// `self` with an equal value.
+    pub fn is_superset_of(&self, other: &ChainMap<K, V>) -> bool
+    where
+        V: PartialEq,
+    {
+        other.is_subset_of(self)
+    }
+
+    /// Removes the top layer and returns it as a new single layer chain.
+    ///
+    /// As with `remove_child`, if only the base layer remains its entries
+    /// are taken and it is left empty.
+    pub fn take_top(&mut self) -> ChainMap<K, V> {
+        ChainMap::new(self.remove_child().unwrap_or_default())
+    }
+
+    /// Removes the top layer and merges its entries into the base layer,
+    /// replacing any base entries with the same key.
+    ///
+    /// Does nothing if the chain only has one layer.
+    pub fn merge_into_base(&mut self) {
+        if self.maps.len() == 1 {
+            return;
+        }
+        if let Some(top) = self.maps.pop() {
+            self.maps[0].extend(top);
+        }
+    }
+
+    /// An iterator visiting the keys of the layer at `depth` that do not
+    /// appear in any other layer.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `depth` is out of bounds.
+    pub fn keys_only_in(&self, depth: usize) -> impl Iterator<Item = &K> {
+        self.view().keys_only_in(depth)
+    }
+
+    /// Consumes the chain, returning a new chain with every entry in every
+    /// layer replaced by the pair `f` returns for it. The layer structure
+    /// is kept.
+    ///
+    /// If `f` produces the same key for two entries in the same layer, only
+    /// one of them is kept and which one is unspecified.
+    pub fn apply_to_all<F>(self, f: F) -> Self
+    where
+        F: Fn(&K, &V) -> (K, V),
+    {
+        let maps = self
+            .maps
+            .iter()
+            .map(|map| map.iter().map(|(k, v)| f(k, v)).collect())
+            .collect();
+        Self { maps }
+    }
+
+    /// Returns every value stored for `key` along with the index of the
+    /// layer it is stored in, starting with the innermost layer.
+    pub fn trace_get<Q>(&self, key: &Q) -> Vec<(usize, &V)>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.view().trace_get(key)
+    }
+
+    /// Returns the top layer, the current scope.
+    pub fn top(&self) -> &HashMap<K, V> {
+        self.view().top()
+    }
+
+    /// An iterator visiting every layer, starting with the base.
+    pub fn layer_iter(&self) -> impl Iterator<Item = &HashMap<K, V>> {
+        self.view().layer_iter()
+    }
+
+    /// An iterator visiting every layer, starting with the top.
+    pub fn rev_layer_iter(&self) -> impl Iterator<Item = &HashMap<K, V>> {
+        self.view().rev_layer_iter()
+    }
+
+    /// Returns the index of every layer containing `key`, starting with the
+    /// innermost layer.
+    pub fn position_all<Q>(&self, key: &Q) -> Vec<usize>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.view().position_all(key)
+    }
+
+    /// Returns the total number of entries stored across every layer,
+    /// counting a shadowed key once for each layer it is in.
+    ///
+    /// Compare with `len`, which counts each visible key once, and
+    /// `local_len`, which counts only the entries in the top layer.
+    pub fn chain_len(&self) -> usize {
+        self.view().chain_len()
+    }
+
+    /// Returns the number of entries in the top layer.
+    pub fn local_len(&self) -> usize {
+        self.view().local_len()
+    }
+
+    /// Inserts every key-value pair from `items` into the top layer,
+    /// returning how many of the keys were not already in the top layer.
+    pub fn insert_many<I>(&mut self, items: I) -> usize
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let top = self.top_layer_mut();
+        let mut added = 0;
+        for (k, v) in items {
+            if top.insert(k, v).is_none() {
+                added += 1;
+            }
+        }
+        added
+    }
+
+    /// Removes every entry from the layer at `index`, returning them as an
+    /// iterator. The layer itself is kept, so the chain's depth does not
+    /// change.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    pub fn drain_layer_at(&mut self, index: usize) -> impl Iterator<Item = (K, V)> + '_ {
+        self.maps[index].drain()
+    }
+
+    /// Replaces the value for `key` in the top layer with `new_value`,
+    /// returning the old value. Parent layers are not considered.
+    ///
+    /// If the top layer does not contain the key, `new_value` is inserted
+    /// under an owned copy of `key` and `None` is returned.
+    pub fn swap_top_with<Q>(&mut self, key: &Q, new_value: V) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ToOwned<Owned = K> + ?Sized,
+    {
+        let top = self.top_layer_mut();
+        match top.get_mut(key) {
+            Some(v) => Some(replace(v, new_value)),
+            None => {
+                top.insert(key.to_owned(), new_value);
+                None
+            }
+        }
+    }
+
+    /// Returns a clone of the value for `key`, or the result of calling
+    /// `default` if no layer contains it. The chain is not modified.
+    pub fn get_or_else<Q, F>(&self, key: &Q, default: F) -> V
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        V: Clone,
+        F: FnOnce() -> V,
+    {
+        self.get(key).cloned().unwrap_or_else(default)
+    }
+
+    /// Retains only the visible entries for which `f` returns true.
+    ///
+    /// Every shadowed entry is removed, as with `dedup_keys`, and then
+    /// `f` is called once for each visible entry. When `f` returns false
+    /// the key is removed from the chain entirely.
+    pub fn retain_visible<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&K, &mut V) -> bool,
+    {
+        self.dedup_keys();
+        for map in &mut self.maps {
+            map.retain(&mut f);
+        }
+    }
+
+    /// An iterator visiting all visible key-value pairs, with mutable
+    /// references to the values. Each key is yielded exactly once, with
+    /// its innermost value.
+    pub fn iter_pairs_mut(&mut self) -> impl Iterator<Item = (&K, &mut V)> {
+        let mut ret = Vec::new();
+        let mut rest = &mut self.maps[..];
+        while let Some((map, above)) = take(&mut rest).split_first_mut() {
+            ret.extend(
+                map.iter_mut()
+                    .filter(|(k, _)| !above.iter().any(|map| map.contains_key(*k))),
+            );
+            rest = above;
+        }
+        ret.into_iter()
+    }
+
+    /// Returns the value for `key` in the top layer, ignoring all parent
+    /// layers.
+    pub fn get_local<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.view().get_local(key)
+    }
+
+    /// Returns a mutable reference to the value for `key` in the top layer,
+    /// ignoring all parent layers.
+    pub fn get_mut_local<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.top_layer_mut().get_mut(key)
+    }
+
+    /// Returns true if any visible value is equal to `value`. Shadowed
+    /// values are not considered.
+    pub fn contains_value<W>(&self, value: &W) -> bool
+    where
+        W: PartialEq<V> + ?Sized,
+    {
+        self.values().any(|v| value == v)
+    }
+
+    /// Returns the key of a visible entry whose value is equal to `value`.
+    ///
+    /// If more than one visible key has an equal value, which one is
+    /// returned is unspecified. This visits every visible entry in the
+    /// worst case.
+    pub fn key_of_value<W>(&self, value: &W) -> Option<&K>
+    where
+        W: PartialEq<V> + ?Sized,
+    {
+        self.iter().find(|(_, v)| value == *v).map(|(k, _)| k)
+    }
+
+    /// Returns the innermost layer for which `f` returns true, along with
+    /// its index.
+    pub fn find_layer<F>(&self, f: F) -> Option<(usize, &HashMap<K, V>)>
+    where
+        F: Fn(&HashMap<K, V>) -> bool,
+    {
+        self.maps.iter().enumerate().rev().find(|(_, map)| f(map))
+    }
+
+    /// Returns a mutable reference to the innermost layer for which `f`
+    /// returns true, along with its index.
+    pub fn find_layer_mut<F>(&mut self, f: F) -> Option<(usize, &mut HashMap<K, V>)>
+    where
+        F: Fn(&HashMap<K, V>) -> bool,
+    {
+        self.maps
+            .iter_mut()
+            .enumerate()
+            .rev()
+            .find(|(_, map)| f(map))
+    }
+
+    /// Returns a clone of the top layer.
+    pub fn clone_top(&self) -> HashMap<K, V>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        self.top().clone()
+    }
+
+    /// Returns a clone of the base layer.
+    pub fn clone_base(&self) -> HashMap<K, V>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        self.maps[0].clone()
+    }
+
+    /// Pushes a clone of the layer at `index` as a new top layer.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    pub fn copy_layer_to_top(&mut self, index: usize)
+    where
+        K: Clone,
+        V: Clone,
+    {
+        let layer = self.maps[index].clone();
+        self.maps.push(layer);
+    }
+
+    /// Returns every key present in both layer `a` and layer `b`, mapped to
+    /// its value in each layer.
+    ///
+    /// # Panics
+    ///
+    /// Panics if either index is out of bounds.
+    pub fn layer_intersection(&self, a: usize, b: usize) -> HashMap<&K, (&V, &V)> {
+        let other = &self.maps[b];
+        self.maps[a]
+            .iter()
+            .filter_map(|(k, v)| other.get(k).map(|w| (k, (v, w))))
+            .collect()
+    }
+
+    /// Returns every entry of layer `a` whose key is not in layer `b`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if either index is out of bounds.
+    pub fn layer_difference(&self, a: usize, b: usize) -> HashMap<&K, &V> {
+        let other = &self.maps[b];
+        self.maps[a]
+            .iter()
+            .filter(|(k, _)| !other.contains_key(*k))
+            .collect()
+    }
+
+    /// Consumes the chain, returning every entry of every layer, including
+    /// shadowed ones. Layers are visited from the base to the top.
+    ///
+    /// Use `into_drain` to get only the visible entries.
+    pub fn into_entries(self) -> impl Iterator<Item = (K, V)> {
+        self.maps.into_iter().flatten()
+    }
+
+    /// Calls `f` with a mutable reference to the base layer.
+    pub fn apply_to_base<F>(&mut self, f: F)
+    where
+        F: FnOnce(&mut HashMap<K, V>),
+    {
+        f(&mut self.maps[0]);
+    }
+
+    /// Inserts `map` as a new base layer, shifting every existing layer up
+    /// by one. The new layer is searched last.
+    pub fn prepend_layer(&mut self, map: HashMap<K, V>) {
+        self.maps.insert(0, map);
+    }
+
+    /// Moves every layer of `other` onto the top of this chain, the base
+    /// of `other` landing directly above the current top.
+    pub fn append_chain(&mut self, other: ChainMap<K, V>) {
+        self.maps.extend(other.maps);
+    }
+
+    /// Pushes a clone of every layer of `other` onto the top of this chain,
+    /// the base of `other` landing directly above the current top.
+    pub fn extend_from_chain(&mut self, other: &ChainMap<K, V>)
+    where
+        K: Clone,
+        V: Clone,
+    {
+        self.maps.extend(other.maps.iter().cloned());
+    }
+
+    /// Pushes a new child layer, calls `f` with the chain, and then removes
+    /// the child layer, returning the result of `f`.
+    ///
+    /// Afterwards, every layer above the chain's original depth is removed,
+    /// including any that `f` pushed, even if `f` panics. Layers that `f`
+    /// removes are not restored, so if `f` pops more layers than it pushes
+    /// the chain is left shallower than it started.
+    pub fn into_scoped<F, R>(&mut self, f: F) -> R
+    where
+        F: FnOnce(&mut Self) -> R,
+    {
+        let depth = self.maps.len();
+        self.new_child();
+        let guard = ScopeGuard {
+            chain_map: self,
+            depth,
+        };
+        f(guard.chain_map)
+    }
+
+    /// An iterator visiting all visible values. This is the same as
+    /// `values`.
+    pub fn all_visible_values(&self) -> impl Iterator<Item = &V> {
+        self.values()
+    }
+
+    /// Writes every layer to `writer`, starting with the base, as a
+    /// `Layer N:` header followed by one indented `key: value` line per
+    /// entry. Entries within a layer are sorted by their formatted key.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()>
+    where
+        K: Display,
+        V: Display,
+    {
+        for (i, map) in self.maps.iter().enumerate() {
+            writeln!(writer, "Layer {}:", i)?;
+            for (k, v) in sorted_entries(map) {
+                writeln!(writer, "  {}: {}", k, v)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Asserts that some layer of the chain contains `key`.
+    ///
+    /// # Panics
+    ///
+    /// Panics, printing every layer that was searched, if no layer
+    /// contains the key.
+    pub fn assert_contains<Q>(&self, key: &Q)
+    where
+        K: Borrow<Q> + Debug,
+        Q: Hash + Eq + Debug + ?Sized,
+        V: Debug,
+    {
+        assert!(
+            self.contains_key(key),
+            "key {:?} not found in any of {} layers: {:?}",
+            key,
+            self.maps.len(),
+            self.maps
+        );
+    }
+
+    /// Asserts that no layer of the chain contains `key`.
+    ///
+    /// # Panics
+    ///
+    /// Panics, listing the layers that contain it, if any layer contains
+    /// the key.
+    pub fn assert_not_contains<Q>(&self, key: &Q)
+    where
+        K: Borrow<Q> + Debug,
+        Q: Hash + Eq + Debug + ?Sized,
+        V: Debug,
+    {
+        let found = self.position_all(key);
+        assert!(
+            found.is_empty(),
+            "key {:?} found in layers {:?}: {:?}",
+            key,
+            found,
+            self.maps
+        );
+    }
+
+    /// Replaces the layer at `index` with the result of calling `f` on it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is out of bounds.
+    pub fn map_layer<F>(&mut self, index: usize, f: F)
+    where
+        F: FnOnce(HashMap<K, V>) -> HashMap<K, V>,
+    {
+        let layer = take(&mut self.maps[index]);
+        self.maps[index] = f(layer);
+    }
+
+    /// Returns the number of distinct visible values.
+    pub fn count_unique_values(&self) -> usize
+    where
+        V: Hash + Eq,
+    {
+        self.values().collect::<HashSet<_>>().len()
+    }
+
+    /// Returns every visible key whose value is equal to `value`, in an
+    /// unspecified order.
+    pub fn find_value<W>(&self, value: &W) -> Vec<&K>
+    where
+        W: PartialEq<V> + ?Sized,
+    {
+        self.iter()
+            .filter(|(_, v)| value == *v)
+            .map(|(k, _)| k)
+            .collect()
+    }
+
+    /// Returns the entries of every layer, including shadowed ones, keyed
+    /// by layer index. Every layer has an entry, even if it is empty.
+    pub fn group_by_layer(&self) -> HashMap<usize, Vec<(&K, &V)>> {
+        self.maps
+            .iter()
+            .enumerate()
+            .map(|(i, map)| (i, map.iter().collect()))
+            .collect()
+    }
+
+    /// Returns true if `check_invariants` finds no problems.
+    ///
+    /// See also `debug_assert_consistent!`, which checks this only in debug
+    /// builds.
+    pub fn is_consistent(&self) -> bool {
+        self.check_invariants().is_ok()
+    }
+
+    /// Returns the index of the innermost layer containing `key`.
+    pub fn highest_layer_with<Q>(&self, key: &Q) -> Option<usize>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.view().highest_layer_with(key)
+    }
+
+    /// Returns the index of the outermost layer containing `key`.
+    pub fn lowest_layer_with<Q>(&self, key: &Q) -> Option<usize>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.view().lowest_layer_with(key)
+    }
+
+    /// Removes layers from the top for as long as `f` returns true for
+    /// them, stopping at the first layer it rejects or when only the base
+    /// remains.
+    ///
+    /// The removed layers are returned with the most recently removed
+    /// first, so they are in the same base-to-top order they had in the
+    /// chain.
+    pub fn take_while_layers<F>(&mut self, f: F) -> Vec<HashMap<K, V>>
+    where
+        F: Fn(&HashMap<K, V>) -> bool,
+    {
+        let mut taken = Vec::new();
+        while self.maps.len() > 1 && f(self.top()) {
+            taken.extend(self.maps.pop());
+        }
+        taken.reverse();
+        taken
+    }
+
+    /// Returns the value for `key` in the base layer, ignoring every layer
+    /// above it.
+    pub fn get_base<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.view().get_base(key)
+    }
+
+    /// Returns a mutable reference to the value for `key` in the base
+    /// layer, ignoring every layer above it.
+    pub fn get_base_mut<Q>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.maps[0].get_mut(key)
+    }
+
+    /// Looks `key` up in the base layer only, skipping any shadowing
+    /// layers. This is the same as `get_base`.
+    pub fn skip_to_base_get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.get_base(key)
+    }
+
+    /// An iterator visiting every entry of every layer, including shadowed
+    /// ones, as `(layer_index, key, value)`. This is the same as
+    /// `iter_with_depth`: all entries of layer 0 come first, then layer 1,
+    /// and so on, in arbitrary order within each layer.
+    pub fn flat_iter_with_layers(&self) -> impl Iterator<Item = (usize, &K, &V)> {
+        self.iter_with_depth()
+    }
+
+    /// Reverses the order of the layers in place, so the base becomes the
+    /// top and the top becomes the base.
+    ///
+    /// This is a destructive reordering: every key defined in more than one
+    /// layer will resolve to a different value afterwards.
+    pub fn reverse_layers(&mut self) {
+        self.maps.reverse();
+    }
+
+    /// Returns the value for `key`, searching only the layers from `from` to
+    /// `to` (inclusive), starting with layer `to`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `from > to` or if `to` is not a valid layer index.
+    pub fn window_get<Q>(&self, key: &Q, from: usize, to: usize) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.layer_range(from, to)
+            .iter()
+            .rev()
+            .find_map(|map| map.get(key))
+    }
+
+    /// Moves the value stored under `old_key` in the top layer to `new_key`,
+    /// overwriting any value `new_key` already had there.
+    ///
+    /// Returns false, leaving the chain unchanged, if `old_key` is not in
+    /// the top layer.
+    pub fn rename_key<Q>(&mut self, old_key: &Q, new_key: K) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let top = self.top_layer_mut();
+        match top.remove(old_key) {
+            Some(value) => {
+                top.insert(new_key, value);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns true if `key` is defined in the top layer, whether or not a
+    /// parent layer also defines it.
+    pub fn is_local<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.view().is_local(key)
+    }
+
+    /// Returns true if `key` is visible in the chain only through a parent
+    /// layer, i.e. it is not defined in the top layer.
+    pub fn is_inherited<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.view().is_inherited(key)
+    }
+
+    /// Copies the top layer's value for `key` into every parent layer,
+    /// overwriting whatever those layers held for it. This is the opposite
+    /// of `copy_to_top`.
+    ///
+    /// Returns false, leaving the chain unchanged, if the key is not in the
+    /// top layer.
+    pub fn propagate_down<Q>(&mut self, key: &Q) -> bool
+    where
+        K: Borrow<Q> + Clone,
+        Q: Hash + Eq + ?Sized,
+        V: Clone,
+    {
+        let (top, parents) = match self.maps.split_last_mut() {
+            Some(split) => split,
+            None => return false,
+        };
+        match top.get_key_value(key) {
+            Some((k, v)) => {
+                for map in parents {
+                    map.insert(k.clone(), v.clone());
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes layers from the top until the chain is `target_len` layers
+    /// deep, returning the removed layers from base to top. A `target_len`
+    /// of 0 is treated as 1, since the base layer is never removed.
+    ///
+    /// Does nothing if the chain is already at or below `target_len`.
+    pub fn shrink_layers_to(&mut self, target_len: usize) -> Vec<HashMap<K, V>> {
+        let target_len = target_len.max(1);
+        if target_len >= self.maps.len() {
+            return Vec::new();
+        }
+        self.maps.split_off(target_len)
+    }
+
+    /// Returns a new chain built by calling `f` on every layer, starting
+    /// with the base. Unlike `map_layers`, this leaves `self` untouched.
+    pub fn layer_map<F>(&self, f: F) -> ChainMap<K, V>
+    where
+        F: FnMut(&HashMap<K, V>) -> HashMap<K, V>,
+    {
+        ChainMap {
+            maps: self.maps.iter().map(f).collect(),
+        }
+    }
+
+    /// Pairs up the layers of `self` and `other`, returning a chain whose
+    /// layer `i` holds `(self_value, other_value)` for every key present in
+    /// layer `i` of both chains.
+    ///
+    /// Returns `None` if the chains are not the same depth.
+    pub fn zip_layers(&self, other: &ChainMap<K, V>) -> Option<ChainMap<K, (V, V)>>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        if self.maps.len() != other.maps.len() {
+            return None;
+        }
+        let maps = self
+            .maps
+            .iter()
+            .zip(&other.maps)
+            .map(|(left, right)| {
+                left.iter()
+                    .filter_map(|(k, v)| {
+                        right
+                            .get(k)
+                            .map(|other_v| (k.clone(), (v.clone(), other_v.clone())))
+                    })
+                    .collect()
+            })
+            .collect();
+        Some(ChainMap { maps })
+    }
+
+    /// An iterator visiting every layer along with its index, starting with
+    /// the base.
+    pub fn enumerate_layers(&self) -> impl Iterator<Item = (usize, &HashMap<K, V>)> {
+        self.view().enumerate_layers()
+    }
+
+    /// An iterator visiting every layer mutably along with its index,
+    /// starting with the base.
+    pub fn enumerate_layers_mut(&mut self) -> impl Iterator<Item = (usize, &mut HashMap<K, V>)> {
+        self.maps.iter_mut().enumerate()
+    }
+
+    /// Collects the visible entries into a vector sorted by key.
+    pub fn visible_pairs_sorted_by_key(&self) -> Vec<(&K, &V)>
+    where
+        K: Ord,
+    {
+        let mut pairs: Vec<_> = self.iter().collect();
+        pairs.sort_by_key(|(k, _)| *k);
+        pairs
+    }
+
+    /// Returns a rough estimate, in bytes, of the memory used by the chain.
+    ///
+    /// This counts `capacity * (size_of::<K>() + size_of::<V>())` for every
+    /// layer plus the size of the layer `Vec` and of each `HashMap`. It
+    /// ignores hashing metadata, allocator overhead and anything owned by
+    /// the keys or values themselves, so it is only an estimate and will
+    /// not match what the allocator actually reports.
+    pub fn total_memory_estimate(&self) -> usize {
+        let entry_size = size_of::<K>() + size_of::<V>();
+        let layers: usize = self
+            .maps
+            .iter()
+            .map(|map| size_of::<HashMap<K, V>>() + map.capacity() * entry_size)
+            .sum();
+        size_of::<Vec<HashMap<K, V>>>() + layers
+    }
+
+    /// Merges the layer at `index + 1` into the layer at `index`, leaving a
+    /// single layer at `index`. Entries from the upper layer replace those
+    /// with the same key in the lower layer, so visible lookups are
+    /// unchanged.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index + 1 >= depth()`.
+    pub fn merge_adjacent_layers(&mut self, index: usize) {
+        assert!(
+            index < self.maps.len().saturating_sub(1),
+            "layer {} has no layer above it",
+            index
+        );
+        let upper = self.maps.remove(index + 1);
+        self.maps[index].extend(upper);
+    }
+
+    /// Records which keys the top layer holds, for use with
+    /// `count_insertions_since_snapshot`. Only the chain's depth and a hash
+    /// of each key are stored, so neither keys nor values are cloned.
+    pub fn snapshot(&self) -> Snapshot<K, V> {
+        let top = self.top();
+        Snapshot {
+            depth: self.maps.len(),
+            key_hashes: top.keys().map(|k| top.hasher().hash_one(k)).collect(),
+            layer: PhantomData,
+        }
+    }
+
+    /// Returns the number of keys in the top layer that were absent when
+    /// `snap` was taken. Overwriting a key that was already there and
+    /// removing keys are not counted.
+    ///
+    /// Returns `None` if the chain's depth has changed since `snap` was
+    /// taken, since the top layer is then a different layer.
+    pub fn count_insertions_since_snapshot(&self, snap: &Snapshot<K, V>) -> Option<usize> {
+        if self.maps.len() != snap.depth {
+            return None;
+        }
+        let top = self.top();
+        let count = top
+            .keys()
+            .filter(|k| !snap.key_hashes.contains(&top.hasher().hash_one(*k)))
+            .count();
+        Some(count)
+    }
+
+    /// Consumes the chain, wrapping it so that every insert is checked
+    /// against `policy`.
+    pub fn with_shadow_policy(self, policy: ShadowPolicy) -> ChainMapConfigured<K, V> {
+        ChainMapConfigured::new(self, policy)
+    }
+
+    /// An iterator visiting each key once, like `iter`, but with lookup
+    /// priority reversed: a key defined in several layers is yielded with
+    /// the value from its outermost layer. Entries are yielded starting
+    /// with the base.
+    pub fn iter_visible_in_reverse(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.view().iter_visible_in_reverse()
+    }
+
+    /// Returns a two-layer copy of the chain: a clone of the base layer,
+    /// with every other layer flattened into a single layer on top of it.
+    /// Visible lookups on the copy match those on `self`.
+    pub fn flatten_except_base(&self) -> ChainMap<K, V>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        let base = self.maps[0].clone();
+        let top = if self.maps.len() > 1 {
+            self.flatten_range(1, self.maps.len() - 1)
+        } else {
+            HashMap::new()
+        };
+        ChainMap {
+            maps: vec![base, top],
+        }
+    }
+
+    /// An iterator visiting every entry hidden by a definition of the same
+    /// key in a layer above it, as `(key, value, layer_index)`. Entries are
+    /// yielded starting with the innermost layer.
+    pub fn iter_shadowed_pairs(&self) -> impl Iterator<Item = (&K, &V, usize)> {
+        self.view().iter_shadowed_pairs()
+    }
+
+    /// Returns true if every key appears in at most one layer. This is the
+    /// same as `is_shadow_free`.
+    pub fn is_strictly_layered(&self) -> bool {
+        self.is_shadow_free()
+    }
+
+    /// Compares the visible entries of `self` against those of `other`,
+    /// sorting every difference into added, removed or changed.
+    pub fn compare_visible<'a>(&'a self, other: &'a ChainMap<K, V>) -> ComparisonResult<'a, K, V>
+    where
+        V: PartialEq,
+    {
+        let mut result = ComparisonResult {
+            added: HashMap::new(),
+            removed: HashMap::new(),
+            changed: HashMap::new(),
+        };
+        for (k, v) in self.iter() {
+            match other.get(k) {
+                Some(other_v) if other_v != v => {
+                    result.changed.insert(k, (v, other_v));
+                }
+                Some(_) => {}
+                None => {
+                    result.removed.insert(k, v);
+                }
+            }
+        }
+        for (k, v) in other.iter() {
+            if !self.contains_key(k) {
+                result.added.insert(k, v);
+            }
+        }
+        result
+    }
+
+    /// Inserts a key-value pair into the top layer only if `key` is already
+    /// defined somewhere in the chain, so the insert can only redefine an
+    /// existing key.
+    ///
+    /// Returns the top layer's previous value for `key`, or `None` if the
+    /// key was not in the top layer or the insert was rejected because the
+    /// key was not in any layer.
+    pub fn insert_shadowing_only(&mut self, key: K, value: V) -> Option<V> {
+        if !self.contains_key(&key) {
+            return None;
+        }
+        self.insert(key, value)
+    }
+
+    /// Returns the value for `key` in the top layer without checking that
+    /// it is there, skipping the search through parent layers and the
+    /// `Option` returned by `get_local`.
+    ///
+    /// # Safety
+    ///
+    /// `key` must be present in the top layer. Calling this with a key that
+    /// is not is undefined behavior. With debug assertions enabled it
+    /// panics instead.
+    pub unsafe fn get_unchecked_top<Q>(&self, key: &Q) -> &V
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let value = self.top().get(key);
+        debug_assert!(
+            value.is_some(),
+            "get_unchecked_top called with a key not in the top layer"
+        );
+        value.unwrap_unchecked()
+    }
+
+    /// Returns the number of layers that contain `key`.
+    pub fn num_layers_with_key<Q>(&self, key: &Q) -> usize
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.view().num_layers_with_key(key)
+    }
+
+    /// An alias for `num_layers_with_key`.
+    pub fn count_layers_containing<Q>(&self, key: &Q) -> usize
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.num_layers_with_key(key)
+    }
+
+    /// Looks `key` up as `get` does and returns the result of calling `f`
+    /// on its value, or `None` if the key is not in any layer.
+    pub fn apply_chain_lookup<Q, F, R>(&self, key: &Q, f: F) -> Option<R>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        F: FnOnce(&V) -> R,
+    {
+        self.get(key).map(f)
+    }
+
+    /// Copies every entry visible from a parent layer into the top layer,
+    /// leaving entries already in the top layer untouched. Afterwards
+    /// `get_local` returns the same result as `get` for every key.
+    pub fn make_top_authoritative(&mut self)
+    where
+        K: Clone,
+        V: Clone,
+    {
+        let (top, parents) = self.maps.split_last_mut().expect("chain map has no layers");
+        for map in parents.iter().rev() {
+            for (k, v) in map {
+                if !top.contains_key(k) {
+                    top.insert(k.clone(), v.clone());
+                }
+            }
+        }
+    }
+
+    /// An iterator visiting every key in the top layer that is also defined
+    /// in at least one parent layer, i.e. the keys the current scope is
+    /// shadowing.
+    pub fn iter_local_keys_shadowing_parent(&self) -> impl Iterator<Item = &K> {
+        self.view().iter_local_keys_shadowing_parent()
+    }
+
+    /// Collapses the top layer and the layer beneath it into a single top
+    /// layer, with the top layer's entries winning on conflict. Visible
+    /// lookups are unchanged.
+    ///
+    /// Does nothing if the chain only has one layer.
+    pub fn absorb_parent(&mut self) {
+        if self.maps.len() == 1 {
+            return;
+        }
+        if let Some(top) = self.maps.pop() {
+            self.top_layer_mut().extend(top);
+        }
+    }
+
+    /// Returns the layer directly beneath the top layer, or `None` if the
+    /// chain only has one layer.
+    pub fn peek_parent(&self) -> Option<&HashMap<K, V>> {
+        self.view().peek_parent()
+    }
+
+    /// Returns a mutable reference to the layer directly beneath the top
+    /// layer, or `None` if the chain only has one layer.
+    pub fn peek_parent_mut(&mut self) -> Option<&mut HashMap<K, V>> {
+        let index = self.maps.len().checked_sub(2)?;
+        self.maps.get_mut(index)
+    }
+
+    /// Moves the entry for `key` out of the top layer and into the layer
+    /// beneath it, replacing any value that layer held for `key`.
+    ///
+    /// Returns false, leaving the chain unchanged, if the key is not in the
+    /// top layer or the chain only has one layer.
+    pub fn transfer_to_parent<Q>(&mut self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let (top, parents) = self.maps.split_last_mut().expect("chain map has no layers");
+        let parent = match parents.last_mut() {
+            Some(parent) => parent,
+            None => return false,
+        };
+        match top.remove_entry(key) {
+            Some((k, v)) => {
+                parent.insert(k, v);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns every visible key that is also in `other`, mapped to
+    /// `(chain_value, other_value)`.
+    pub fn intersect_with_map<'a>(
+        &'a self,
+        other: &'a HashMap<K, V>,
+    ) -> HashMap<&'a K, (&'a V, &'a V)> {
+        self.iter()
+            .filter_map(|(k, v)| other.get(k).map(|other_v| (k, (v, other_v))))
+            .collect()
+    }
+
+    /// Removes every key in `keys` from the top layer, returning how many
+    /// entries were removed. Parent layers are not changed.
+    pub fn subtract_map<Q>(&mut self, keys: &HashSet<Q>) -> usize
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        let top = self.top_layer_mut();
+        keys.iter().filter(|k| top.remove(*k).is_some()).count()
+    }
+
+    /// Returns mutable references to the values of several keys at once,
+    /// each found as `get_mut` would find it.
+    ///
+    /// Fails with `DuplicateKeyError::Duplicate` if any two of `keys` are
+    /// equal, since they would refer to the same value, and with
+    /// `DuplicateKeyError::Missing` if a key is not in any layer.
+    pub fn try_get_many_mut<Q, const N: usize>(
+        &mut self,
+        keys: [&Q; N],
+    ) -> Result<[&mut V; N], DuplicateKeyError>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let mut layers = [0; N];
+        for (index, key) in keys.iter().enumerate() {
+            if keys[index + 1..].contains(key) {
+                return Err(DuplicateKeyError::Duplicate);
+            }
+            layers[index] = self
+                .highest_layer_with(*key)
+                .ok_or(DuplicateKeyError::Missing { index })?;
+        }
+        let ptrs: [*mut V; N] = std::array::from_fn(|i| {
+            let v = self.maps[layers[i]].get_mut(keys[i]);
+            v.expect("key was found in this layer") as *mut V
+        });
+        // SAFETY: the keys are distinct, so no two pointers refer to the same
+        // entry, and `get_mut` never moves an entry, so each pointer stays
+        // valid for as long as `self` is mutably borrowed.
+        Ok(ptrs.map(|v| unsafe { &mut *v }))
+    }
+
+    /// Calls `f` with the layer index and value of every occurrence of
+    /// `key`, starting with the innermost layer, and returns the first
+    /// `Some` it produces.
+    pub fn depth_search<Q, F, R>(&self, key: &Q, f: F) -> Option<R>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        F: Fn(usize, &V) -> Option<R>,
+    {
+        self.view().depth_search(key, f)
+    }
+
+    /// Returns every entry of layers `a` and `b`, with the entries of `b`
+    /// replacing those of `a` that have the same key. The keys are cloned
+    /// and the values borrowed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `a` or `b` is out of bounds.
+    pub fn layer_union(&self, a: usize, b: usize) -> HashMap<K, &V>
+    where
+        K: Clone,
+    {
+        let (a, b) = (&self.maps[a], &self.maps[b]);
+        a.iter().chain(b).map(|(k, v)| (k.clone(), v)).collect()
+    }
+
+    /// An iterator visiting every layer except the base, starting with the
+    /// layer directly above it.
+    pub fn iter_child_layers(&self) -> impl Iterator<Item = &HashMap<K, V>> {
+        self.view().iter_child_layers()
+    }
+
+    /// An iterator visiting every layer except the base mutably, starting
+    /// with the layer directly above it.
+    pub fn iter_child_layers_mut(&mut self) -> impl Iterator<Item = &mut HashMap<K, V>> {
+        self.maps.iter_mut().skip(1)
+    }
+
+    /// Returns true if the top layer has no entries, regardless of what the
+    /// parent layers contain.
+    pub fn local_is_empty(&self) -> bool {
+        self.view().local_is_empty()
+    }
+
+    /// Removes every layer except the base and clears the base, leaving the
+    /// chain equivalent to `ChainMap::default()` but keeping the base
+    /// layer's allocation.
+    pub fn clear_non_base_layers(&mut self) {
+        self.maps.truncate(1);
+        self.maps[0].clear();
+    }
+
+    /// Folds every layer into an accumulator with `f`, starting with the
+    /// base, in the style of `Iterator::fold`.
+    pub fn fold_layers<B, F>(&self, init: B, f: F) -> B
+    where
+        F: Fn(B, &HashMap<K, V>) -> B,
+    {
+        self.maps.iter().fold(init, f)
+    }
+
+    /// Retains only the entries for which `f` returns true, in every layer.
+    /// Unlike `retain_visible`, shadowed entries are kept and checked too,
+    /// so `f` may be called once for each layer a key appears in.
+    pub fn global_retain<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&K, &mut V) -> bool,
+    {
+        for map in &mut self.maps {
+            map.retain(&mut f);
+        }
+    }
+
+    /// Returns the number of visible entries for which `f` returns true.
+    /// This is the same as `count_if`.
+    pub fn count_visible_by<F>(&self, f: F) -> usize
+    where
+        F: Fn(&K, &V) -> bool,
+    {
+        self.count_if(f)
+    }
+
+    /// Returns the first visible entry for which `f` returns true, in the
+    /// same order as `iter`. This is the same as `find`.
+    pub fn first_visible<F>(&self, f: F) -> Option<(&K, &V)>
+    where
+        F: Fn(&K, &V) -> bool,
+    {
+        self.find(f)
+    }
+
+    /// Returns the last visible entry for which `f` returns true, in the
+    /// same order as `iter`. Every visible entry is checked.
+    pub fn last_visible<F>(&self, f: F) -> Option<(&K, &V)>
+    where
+        F: Fn(&K, &V) -> bool,
+    {
+        self.iter().filter(|(k, v)| f(k, v)).last()
+    }
+
+    /// Inserts every key-value pair from `iter` into the layer at `depth`,
+    /// replacing any values that layer already held for the same keys.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `depth` is not less than `depth()`.
+    pub fn insert_batch_into_layer<I>(&mut self, depth: usize, iter: I)
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        self.maps[depth].extend(iter);
+    }
+
+    /// An iterator visiting every key in the top layer that is not defined
+    /// in any parent layer, i.e. the keys the current scope introduced.
+    /// This is the complement of `iter_local_keys_shadowing_parent`.
+    pub fn keys_added_in_top(&self) -> impl Iterator<Item = &K> {
+        self.view().keys_added_in_top()
+    }
+
+    /// Returns the number of times `key` is shadowed: one less than the
+    /// number of layers containing it, or 0 if no layer contains it.
+    pub fn shadow_depth<Q>(&self, key: &Q) -> usize
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.view().shadow_depth(key)
+    }
+
+    /// Returns the base layer.
+    pub fn base(&self) -> &HashMap<K, V> {
+        self.view().base()
+    }
+
+    /// Moves the top layer to the base, shifting every other layer up by
+    /// one. This is `rotate_right(1)`, and does nothing if the chain has
+    /// only one layer.
+    pub fn rotate_top_to_base(&mut self) {
+        self.rotate_right(1);
+    }
+
+    /// Moves the base layer to the top, shifting every other layer down by
+    /// one. This is `rotate_left(1)`, and does nothing if the chain has only
+    /// one layer.
+    pub fn rotate_base_to_top(&mut self) {
+        self.rotate_left(1);
+    }
+
+    /// Returns the result of calling `f` with the result of `get(key)`.
+    pub fn for_key<Q, R, F>(&self, key: &Q, f: F) -> R
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        F: FnOnce(Option<&V>) -> R,
+    {
+        f(self.get(key))
+    }
+
+    /// Pushes `scope_count` new, empty, layers onto the chain and inserts
+    /// every entry from `entries` into the new top layer. If `scope_count`
+    /// is 0 the entries are inserted into the existing top layer.
+    pub fn multi_scope_insert<I>(&mut self, entries: I, scope_count: usize)
+    where
+        I: IntoIterator<Item = (K, V)>,
+    {
+        for _ in 0..scope_count {
+            self.new_child();
+        }
+        self.top_layer_mut().extend(entries);
+    }
+
+    /// Returns the innermost layer containing `key`, i.e. the layer `get`
+    /// would take its value from. `highest_layer_with` returns that layer's
+    /// index instead.
+    pub fn effective_layer_for<Q>(&self, key: &Q) -> Option<&HashMap<K, V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.view().effective_layer_for(key)
+    }
+
+    /// Returns a new chain holding clones of the layers `from..=to`, where
+    /// layer 0 is the base.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `from > to` or `to` is out of bounds.
+    pub fn clone_range(&self, from: usize, to: usize) -> ChainMap<K, V>
+    where
+        K: Clone,
+        V: Clone,
+    {
+        ChainMap {
+            maps: self.layer_range(from, to).to_vec(),
+        }
+    }
+
+    /// The layers `from..=to`, panicking unless that is a non-empty range
+    /// of valid layer indices.
+    fn layer_range(&self, from: usize, to: usize) -> &[HashMap<K, V>] {
+        assert!(
+            from <= to && to < self.maps.len(),
+            "invalid layer range {}..={} for a chain of depth {}",
+            from,
+            to,
+            self.maps.len()
+        );
+        &self.maps[from..=to]
+    }
+
+    pub(crate) fn top_layer_mut(&mut self) -> &mut HashMap<K, V> {
+        self.maps.last_mut().expect("chain map has no layers")
+    }
+}
+
+impl<K: Hash + Eq, V: Default> ChainMap<K, V> {
+    /// Returns a mutable reference to the value for `key` in the top layer,
+    /// inserting `V::default()` there first if the top layer does not
+    /// contain it. Parent layers are never consulted.
+    pub fn get_or_default_insert(&mut self, key: K) -> &mut V {
+        self.top_layer_mut().entry(key).or_default()
+    }
+}
+
+impl<K: Hash + Eq, V: PartialEq> ChainMap<K, V> {
+    /// Returns true if inserting `new_value` for `key` would change the top
+    /// layer, i.e. the top layer does not already hold an equal value for
+    /// `key`. Parent layers are not consulted.
+    pub fn value_changed_in_top<Q>(&self, key: &Q, new_value: &V) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.get_local(key) != Some(new_value)
+    }
+}
+
+impl<K: Hash + Eq, V> Default for ChainMap<K, V> {
+    /// Creates a chain with a single, empty, base layer.
+    fn default() -> Self {
+        Self {
+            maps: vec![HashMap::new()],
+        }
+    }
+}
+
+impl<K, Q: ?Sized, V> Index<&Q> for ChainMap<K, V>
+where
+    K: Eq + Hash + Borrow<Q>,
+    Q: Eq + Hash,
+{
+    type Output = V;
+
+    /// Returns a reference to the value corresponding to the supplied key.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the key is not present in the `HashMap`.
+    #[inline]
+    fn index(&self, key: &Q) -> &V {
+        self.get(key).expect("no entry found for key")
+    }
+}
+
+impl<K: Hash + Eq, V> Index<usize> for ChainMap<K, V> {
+    type Output = HashMap<K, V>;
+
+    /// Returns a reference to the layer at `index`, where 0 is the base.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is not less than `depth()`.
+    #[inline]
+    fn index(&self, index: usize) -> &HashMap<K, V> {
+        &self.maps[index]
+    }
+}
+
+impl<K: Hash + Eq, V> IndexMut<usize> for ChainMap<K, V> {
+    /// Returns a mutable reference to the layer at `index`, where 0 is the
+    /// base.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is not less than `depth()`.
+    #[inline]
+    fn index_mut(&mut self, index: usize) -> &mut HashMap<K, V> {
+        &mut self.maps[index]
+    }
+}
+
+impl<K: Hash + Eq, V> AsRef<[HashMap<K, V>]> for ChainMap<K, V> {
+    /// Borrows the layers as a slice, starting with the base.
+    fn as_ref(&self) -> &[HashMap<K, V>] {
+        &self.maps
+    }
+}
+
+impl<K: Hash + Eq, V> AsMut<[HashMap<K, V>]> for ChainMap<K, V> {
+    /// Borrows the layers as a mutable slice, starting with the base. A
+    /// slice can't change length, so the chain keeps at least one layer.
+    fn as_mut(&mut self) -> &mut [HashMap<K, V>] {
+        &mut self.maps
+    }
+}
+
+impl<K: Hash + Eq, V> From<ChainMap<K, V>> for HashMap<K, V> {
+    /// Flattens the chain into a single map, keeping the innermost value
+    /// for each key.
+    fn from(chain_map: ChainMap<K, V>) -> Self {
+        let mut ret = HashMap::new();
+        for map in chain_map.maps {
+            ret.extend(map);
+        }
+        ret
+    }
+}
+
+impl<K: Hash + Eq, V> Extend<HashMap<K, V>> for ChainMap<K, V> {
+    /// Pushes each map as a new layer, the last map becoming the new top.
+    fn extend<I: IntoIterator<Item = HashMap<K, V>>>(&mut self, iter: I) {
+        self.maps.extend(iter);
+    }
+}
+
+impl<K: Hash + Eq, V> FromIterator<HashMap<K, V>> for ChainMap<K, V> {
+    /// Creates a chain with one layer per map, the first being the base.
+    /// An empty iterator produces `ChainMap::default()`.
+    fn from_iter<I: IntoIterator<Item = HashMap<K, V>>>(iter: I) -> Self {
+        Self::new_with_maps(iter.into_iter().collect()).unwrap_or_default()
+    }
+}
+
+impl<K: Hash + Eq, V> Sum for ChainMap<K, V> {
+    /// Stacks the layers of every chain in order, so the top layer of the
+    /// last chain becomes the new top. An empty iterator produces
+    /// `ChainMap::default()`.
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        let maps = iter.flat_map(|chain_map| chain_map.maps).collect();
+        Self::new_with_maps(maps).unwrap_or_default()
+    }
+}
+
+impl<K: Hash + Eq, V> IntoIterator for ChainMap<K, V> {
+    type Item = HashMap<K, V>;
+    type IntoIter = std::vec::IntoIter<HashMap<K, V>>;
+
+    /// Consumes the chain, returning each layer from the base to the top.
+    fn into_iter(self) -> Self::IntoIter {
+        self.maps.into_iter()
+    }
+}
+
+/// Formats the chain in the style of Python's `ChainMap`, with each layer
+/// printed from the base to the top. Keys are quoted, and the entries
+/// within a layer are sorted by key so the output is deterministic.
+///
+/// ```
+/// use hash_chain::ChainMap;
+///
+/// let mut chain_map = ChainMap::default();
+/// chain_map.insert("a", 1);
+/// chain_map.new_child();
+/// chain_map.insert("b", 3);
+/// chain_map.insert("a", 2);
+/// assert_eq!(chain_map.to_string(), "ChainMap({'a': 1}, {'a': 2, 'b': 3})");
+/// ```
+impl<K: Display + Ord, V: Display> Display for ChainMap<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("ChainMap(")?;
+        for (i, map) in self.maps.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            f.write_str("{")?;
+            let mut entries: Vec<_> = map.iter().collect();
+            entries.sort_by_key(|(k, _)| *k);
+            for (j, (k, v)) in entries.into_iter().enumerate() {
+                if j > 0 {
+                    f.write_str(", ")?;
+                }
+                write!(f, "'{}': {}", k, v)?;
+            }
+            f.write_str("}")?;
+        }
+        f.write_str(")")
+    }
+}
+
+/// The entries of `map` with their keys formatted, sorted by key.
+fn sorted_entries<K: Display, V>(map: &HashMap<K, V>) -> Vec<(String, &V)> {
+    let mut entries: Vec<_> = map.iter().map(|(k, v)| (k.to_string(), v)).collect();
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+    entries
+}
+
+/// Truncates a chain to at most `depth` layers when dropped.
+struct ScopeGuard<'a, K, V> {
+    chain_map: &'a mut ChainMap<K, V>,
+    depth: usize,
+}
+
+impl<'a, K, V> Drop for ScopeGuard<'a, K, V> {
+    fn drop(&mut self) {
+        self.chain_map.maps.truncate(self.depth);
+    }
+}
+
+/// The keys of a chain's top layer, recorded by `ChainMap::snapshot`.
+pub struct Snapshot<K, V> {
+    depth: usize,
+    key_hashes: HashSet<u64>,
+    layer: PhantomData<fn() -> HashMap<K, V>>,
+}
+
+impl<K, V> Snapshot<K, V> {
+    /// Returns the number of entries the top layer had when the snapshot
+    /// was taken.
+    pub fn top_len(&self) -> usize {
+        self.key_hashes.len()
+    }
+
+    /// Returns the depth of the chain when the snapshot was taken.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+}
+
+impl<K, V> Clone for Snapshot<K, V> {
+    fn clone(&self) -> Self {
+        Snapshot {
+            depth: self.depth,
+            key_hashes: self.key_hashes.clone(),
+            layer: PhantomData,
+        }
+    }
+}
+
+impl<K, V> Debug for Snapshot<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Snapshot")
+            .field("depth", &self.depth)
+            .field("top_len", &self.top_len())
+            .finish()
+    }
+}
+
+/// The differences between two chains' visible entries, returned by
+/// `ChainMap::compare_visible`.
+#[derive(Debug, Clone)]
+pub struct ComparisonResult<'a, K, V> {
+    /// Entries visible in the other chain but not in this one.
+    pub added: HashMap<&'a K, &'a V>,
+    /// Entries visible in this chain but not in the other one.
+    pub removed: HashMap<&'a K, &'a V>,
+    /// Keys visible in both chains with different values, as
+    /// `(this_value, other_value)`.
+    pub changed: HashMap<&'a K, (&'a V, &'a V)>,
+}
+
+impl<'a, K, V> ComparisonResult<'a, K, V> {
+    /// Returns true if the two chains had no differences.
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// A broken internal invariant, returned by `ChainMap::check_invariants`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvariantViolation {
+    /// The chain has no layers.
+    EmptyLayerVec,
+}
+
+impl Display for InvariantViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InvariantViolation::EmptyLayerVec => f.write_str("chain map has no layers"),
+        }
+    }
+}
+
+impl Error for InvariantViolation {}
+
+/// The error returned by `ChainMap::try_get_many_mut`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DuplicateKeyError {
+    /// The same key was requested more than once.
+    Duplicate,
+    /// The key at `index` of the request is not in any layer.
+    Missing { index: usize },
+}
+
+impl Display for DuplicateKeyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DuplicateKeyError::Duplicate => {
+                f.write_str("the same key was requested more than once")
+            }
+            DuplicateKeyError::Missing { index } => {
+                write!(f, "key {} is not in any layer", index)
+            }
+        }
+    }
+}
+
+impl Error for DuplicateKeyError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::default::Default;
+
+    #[test]
+    fn initialization() {
+        let mut test_map = HashMap::new();
+        test_map.insert("test", 1);
+        let chain_map = ChainMap::new(test_map);
+
+        assert!(!chain_map.maps.is_empty());
+        assert_eq!(chain_map.maps[0].get("test"), Some(&1));
+    }
+
+    #[test]
+    fn initialization_default() {
+        let chain_map: ChainMap<(), ()> = ChainMap::default();
+
+        assert!(!chain_map.maps.is_empty());
+        assert!(chain_map.maps[0].is_empty());
+        assert_eq!(chain_map.depth(), 1);
+    }
+
+    #[test]
+    fn insert() {
+        let mut chain_map = ChainMap::default();
+        assert!(chain_map.insert("test", 1).is_none());
+
+        assert_eq!(chain_map.maps[0].get("test"), Some(&1));
+    }
+
+    #[test]
+    fn get() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("test", 1);
+
+        assert_eq!(chain_map.get(&"test"), Some(&1));
+    }
+
+    #[test]
+    fn get_none() {
+        let chain_map: ChainMap<&str, ()> = ChainMap::default();
+        assert_eq!(chain_map.get(&"test"), None);
+    }
+
+    #[test]
+    fn get_mut() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("test", 1);
+
+        let test_value = chain_map.get_mut(&"test");
+        assert_eq!(test_value, Some(&mut 1));
+        *test_value.unwrap() += 1;
+        let changed = chain_map.get(&"test");
+        assert_eq!(changed, Some(&2));
+    }
+
+    #[test]
+    fn get_mut_outer() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("outer", 1);
+        chain_map.new_child();
+        chain_map.insert("inner", 2);
+        let ret = chain_map.get_mut("outer").unwrap();
+        *ret += 9000;
+
+        let changed = chain_map.get(&"outer");
+        assert_eq!(changed, Some(&9001));
+    }
+
+    #[test]
+    fn index() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("test", 1);
+
+        assert_eq!(chain_map[&"test"], 1);
+    }
+
+    #[test]
+    fn new_child() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("test", 1);
+        chain_map.new_child();
+        assert!(chain_map.maps.len() > 1);
+    }
+
+    #[test]
+    fn scopes() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.insert("y", 2);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        assert_eq!(chain_map.get("x"), Some(&1));
+        assert_eq!(chain_map.get("y"), Some(&2));
+    }
+
+    #[test]
+    fn remove_child() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.insert("y", 2);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        let ret = chain_map.remove_child().unwrap();
+        assert_eq!(ret.get("x"), Some(&1));
+        assert_eq!(chain_map.get("x"), Some(&0));
+    }
+
+    #[test]
+    fn remove_child_length_1() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        let _ = chain_map.remove_child();
+        assert_eq!(chain_map.get("x"), None);
+        assert!(chain_map.maps.len() == 1);
+    }
+
+    #[test]
+    fn len() {
+        let mut chain_map = ChainMap::default();
+        assert!(chain_map.is_empty());
+        chain_map.insert("x", 0);
+        chain_map.insert("y", 2);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        assert_eq!(chain_map.len(), 2);
+        assert_eq!(chain_map.count(), 2);
+        assert!(!chain_map.is_empty());
+    }
+
+    #[test]
+    fn count_if() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.insert("y", 2);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        assert_eq!(chain_map.count_if(|_, v| *v > 0), 2);
+        assert_eq!(chain_map.count_if(|k, _| *k == "x"), 1);
+    }
+
+    #[test]
+    fn iter() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.insert("y", 2);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        let mut pairs: Vec<_> = chain_map.iter().collect();
+        pairs.sort();
+        assert_eq!(pairs, vec![(&"x", &1), (&"y", &2)]);
+    }
+
+    #[test]
+    fn min_max_by_key() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 10);
+        chain_map.insert("y", 5);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        chain_map.insert("z", 7);
+        assert_eq!(chain_map.min_by_key(|v| *v), Some((&"x", &1)));
+        assert_eq!(chain_map.max_by_key(|v| *v), Some((&"z", &7)));
+        let empty: ChainMap<&str, i32> = ChainMap::default();
+        assert_eq!(empty.min_by_key(|v| *v), None);
+    }
+
+    #[test]
+    fn min_max_by() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 10);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        chain_map.insert("y", 3);
+        assert_eq!(chain_map.min_by(|a, b| b.cmp(a)), Some((&"y", &3)));
+        assert_eq!(chain_map.max_by(|a, b| b.cmp(a)), Some((&"x", &1)));
+    }
+
+    #[test]
+    fn invert() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.insert("y", 1);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        let inverted = chain_map.invert();
+        assert_eq!(inverted.maps.len(), 1);
+        assert_eq!(inverted.get(&1), Some(&"x"));
+        assert_eq!(inverted.get(&0), None);
+        assert_eq!(inverted.len(), 1);
+    }
+
+    #[test]
+    fn contains_key() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        assert!(chain_map.contains_key("x"));
+        assert!(!chain_map.contains_key("y"));
+    }
+
+    #[test]
+    fn depth() {
+        let mut chain_map: ChainMap<&str, ()> = ChainMap::default();
+        assert_eq!(chain_map.depth(), 1);
+        chain_map.new_child();
+        assert_eq!(chain_map.depth(), 2);
+    }
+
+    #[test]
+    fn shrink_to() {
+        let mut chain_map = ChainMap::new(HashMap::with_capacity(100));
+        chain_map.insert("x", 0);
+        chain_map.new_child_with(HashMap::with_capacity(100));
+        chain_map.shrink_to(10);
+        for map in &chain_map.maps {
+            assert!(map.capacity() >= 10);
+            assert!(map.capacity() < 100);
+        }
+        chain_map.shrink_to_fit();
+        assert!(chain_map.maps[0].capacity() >= 1);
+        assert!(chain_map.maps[0].capacity() < 10);
+        assert_eq!(chain_map.get("x"), Some(&0));
+    }
+
+    #[test]
+    fn layer_capacities() {
+        let mut chain_map: ChainMap<&str, i32> = ChainMap::new(HashMap::with_capacity(16));
+        chain_map.new_child();
+        let capacities = chain_map.layer_capacities();
+        assert_eq!(capacities.len(), 2);
+        assert!(capacities[0] >= 16);
+        assert_eq!(capacities[1], 0);
+    }
+
+    #[test]
+    fn layer_lengths() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.insert("y", 0);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        assert_eq!(chain_map.layer_lengths(), vec![2, 1]);
+    }
+
+    #[test]
+    fn drain() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.insert("y", 2);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        let mut drained: Vec<_> = chain_map.drain().collect();
+        drained.sort();
+        assert_eq!(drained, vec![("x", 1), ("y", 2)]);
+        assert!(chain_map.is_empty());
+        assert_eq!(chain_map.depth(), 2);
+    }
+
+    #[test]
+    fn into_drain() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        let drained: Vec<_> = chain_map.into_drain().collect();
+        assert_eq!(drained, vec![("x", 1)]);
+    }
+
+    #[test]
+    fn partition() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.insert("y", 2);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        chain_map.insert("z", 3);
+        let (odd, even) = chain_map.partition(|_, v| v % 2 == 1);
+        assert_eq!(odd.len() + even.len(), chain_map.len());
+        for (k, v) in chain_map.iter() {
+            let side = if v % 2 == 1 { &odd } else { &even };
+            assert_eq!(side.get(k), Some(v));
+        }
+    }
+
+    #[test]
+    fn zip_with() {
+        let mut values = ChainMap::default();
+        values.insert("x", 0);
+        values.insert("y", 2);
+        values.new_child();
+        values.insert("x", 1);
+        let mut types = ChainMap::default();
+        types.insert("x", "int");
+        types.insert("z", "str");
+        let zipped = values.zip_with(&types, |v, t| format!("{}: {}", v, t));
+        assert_eq!(zipped.depth(), 1);
+        assert_eq!(zipped.len(), 1);
+        assert_eq!(zipped.get("x"), Some(&"1: int".to_string()));
+    }
+
+    #[test]
+    fn insert_unique() {
+        let mut chain_map = ChainMap::default();
+        assert_eq!(chain_map.insert_unique("x", 0), Ok(()));
+        assert_eq!(chain_map.insert_unique("x", 1), Err(("x", 1)));
+        chain_map.new_child();
+        assert_eq!(chain_map.insert_unique("x", 2), Err(("x", 2)));
+        assert_eq!(chain_map.insert_unique("y", 3), Ok(()));
+        assert_eq!(chain_map.get("x"), Some(&0));
+        assert_eq!(chain_map.maps[1].get("y"), Some(&3));
+    }
+
+    #[test]
+    fn move_to_top() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        chain_map.new_child();
+        assert!(chain_map.move_to_top("x"));
+        assert_eq!(chain_map.maps[2].get("x"), Some(&1));
+        assert_eq!(chain_map.maps[1].get("x"), None);
+        assert_eq!(chain_map.maps[0].get("x"), Some(&0));
+        assert!(!chain_map.move_to_top("x"));
+        assert!(!chain_map.move_to_top("y"));
+    }
+
+    #[test]
+    fn iter_with_depth() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.insert("y", 2);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        let mut entries: Vec<_> = chain_map.iter_with_depth().collect();
+        entries.sort();
+        assert_eq!(entries, vec![(0, &"x", &0), (0, &"y", &2), (1, &"x", &1)]);
+    }
+
+    #[test]
+    fn display() {
+        let mut chain_map = ChainMap::default();
+        assert_eq!(chain_map.to_string(), "ChainMap({})");
+        chain_map.insert("y", 2);
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        assert_eq!(chain_map.to_string(), "ChainMap({'x': 0, 'y': 2}, {})");
+        chain_map.insert("x", 1);
+        assert_eq!(
+            chain_map.to_string(),
+            "ChainMap({'x': 0, 'y': 2}, {'x': 1})"
+        );
+    }
+
+    #[test]
+    fn display_sorts_by_key() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert(10, "ten");
+        chain_map.insert(2, "two");
+        assert_eq!(chain_map.to_string(), "ChainMap({'2': two, '10': ten})");
+    }
+
+    #[test]
+    fn find() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.insert("y", 2);
+        chain_map.new_child();
+        chain_map.insert("x", 3);
+        assert_eq!(chain_map.find(|_, v| *v > 1), Some((&"x", &3)));
+        assert_eq!(chain_map.find(|_, v| *v == 0), None);
+        assert_eq!(chain_map.find(|_, v| *v > 5), None);
+    }
+
+    #[test]
+    fn rfind() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.insert("y", 2);
+        chain_map.new_child();
+        chain_map.insert("x", 3);
+        assert_eq!(chain_map.rfind(|_, v| *v > 1), Some((&"y", &2)));
+        assert_eq!(chain_map.rfind(|_, v| *v == 0), None);
+    }
+
+    #[test]
+    fn sort_layers_by() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.insert("y", 0);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        assert_eq!(chain_map.get("x"), Some(&1));
+        chain_map.sort_layers_by(|a, b| a.len().cmp(&b.len()));
+        assert_eq!(chain_map.depth(), 2);
+        assert_eq!(chain_map.get("x"), Some(&0));
+    }
+
+    #[test]
+    fn flatten_range() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("builtin", 0);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        chain_map.new_child();
+        chain_map.insert("x", 2);
+        chain_map.insert("y", 2);
+        let flat = chain_map.flatten_range(1, 2);
+        assert_eq!(flat.len(), 2);
+        assert_eq!(flat.get("x"), Some(&2));
+        assert_eq!(flat.get("y"), Some(&2));
+        assert_eq!(chain_map.flatten_range(0, 1).get("x"), Some(&1));
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid layer range")]
+    fn flatten_range_reversed() {
+        let mut chain_map: ChainMap<&str, i32> = ChainMap::default();
+        chain_map.new_child();
+        chain_map.flatten_range(1, 0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn flatten_range_out_of_bounds() {
+        let chain_map: ChainMap<&str, ()> = ChainMap::default();
+        chain_map.flatten_range(0, 1);
+    }
+
+    #[test]
+    fn remove_child_keeps_a_layer() {
+        let mut chain_map: ChainMap<&str, ()> = ChainMap::default();
+        chain_map.new_child();
+        for _ in 0..3 {
+            chain_map.remove_child();
+            assert_eq!(chain_map.depth(), 1);
+        }
+    }
+
+    #[test]
+    fn collect_visible() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.insert("y", 2);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        let visible = chain_map.collect_visible();
+        assert_eq!(visible.len(), 2);
+        for (k, v) in &visible {
+            assert_eq!(chain_map.get(*k), Some(*v));
+        }
+    }
+
+    #[test]
+    fn histogram() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.insert("y", 2);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        assert_eq!(chain_map.layer_sizes(), vec![2, 1]);
+        let histogram = chain_map.histogram();
+        assert_eq!(
+            histogram.into_iter().collect::<Vec<_>>(),
+            vec![("x", 2), ("y", 1)]
+        );
+    }
+
+    #[test]
+    fn rotate() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        chain_map.new_child();
+        chain_map.insert("x", 2);
+        chain_map.rotate_left(1);
+        assert_eq!(chain_map.maps[0].get("x"), Some(&1));
+        assert_eq!(chain_map.get("x"), Some(&0));
+        chain_map.rotate_right(1);
+        assert_eq!(chain_map.maps[0].get("x"), Some(&0));
+        assert_eq!(chain_map.get("x"), Some(&2));
+    }
+
+    #[test]
+    fn remove_layer_at() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        chain_map.insert("y", 1);
+        chain_map.new_child();
+        chain_map.insert("z", 2);
+        let removed = chain_map.remove_layer_at(1).unwrap();
+        assert_eq!(removed.get("y"), Some(&1));
+        assert_eq!(chain_map.depth(), 2);
+        assert_eq!(chain_map.maps[0].get("x"), Some(&0));
+        assert_eq!(chain_map.maps[1].get("z"), Some(&2));
+        assert!(chain_map.remove_layer_at(2).is_none());
+        assert!(chain_map.remove_layer_at(0).is_some());
+        assert!(chain_map.remove_layer_at(0).is_none());
+        assert_eq!(chain_map.get("z"), Some(&2));
+    }
+
+    #[test]
+    fn insert_layer_at() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        chain_map.insert("x", 2);
+        let mut middle = HashMap::new();
+        middle.insert("x", 1);
+        middle.insert("y", 1);
+        chain_map.insert_layer_at(1, middle);
+        assert_eq!(chain_map.depth(), 3);
+        assert_eq!(chain_map.maps[1].get("y"), Some(&1));
+        assert_eq!(chain_map.get("x"), Some(&2));
+        let mut top = HashMap::new();
+        top.insert("x", 3);
+        chain_map.insert_layer_at(3, top);
+        assert_eq!(chain_map.get("x"), Some(&3));
+    }
+
+    #[test]
+    #[should_panic]
+    fn insert_layer_at_out_of_bounds() {
+        let mut chain_map: ChainMap<&str, ()> = ChainMap::default();
+        chain_map.insert_layer_at(2, HashMap::new());
+    }
+
+    #[test]
+    fn replace_layer_at() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        chain_map.insert("y", 1);
+        let mut reloaded = HashMap::new();
+        reloaded.insert("x", 9);
+        let old = chain_map.replace_layer_at(0, reloaded).unwrap();
+        assert_eq!(old.get("x"), Some(&0));
+        assert_eq!(chain_map.depth(), 2);
+        assert_eq!(chain_map.get("x"), Some(&9));
+        assert_eq!(chain_map.get("y"), Some(&1));
+    }
+
+    #[test]
+    fn dedup_keys() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.insert("y", 0);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        chain_map.new_child();
+        chain_map.insert("x", 2);
+        chain_map.insert("y", 2);
+        chain_map.dedup_keys();
+        assert_eq!(chain_map.depth(), 3);
+        assert_eq!(chain_map.layer_lengths(), vec![0, 0, 2]);
+        assert_eq!(chain_map.get("x"), Some(&2));
+        assert_eq!(chain_map.get("y"), Some(&2));
+        let total: usize = chain_map.layer_lengths().iter().sum();
+        assert_eq!(total, chain_map.len());
+    }
+
+    #[test]
+    fn shadowed_keys() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.insert("y", 0);
+        assert!(chain_map.is_shadow_free());
+        chain_map.assert_no_shadowing();
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        chain_map.new_child();
+        chain_map.insert("x", 2);
+        assert_eq!(chain_map.shadowed_keys().collect::<Vec<_>>(), vec![&"x"]);
+        assert!(!chain_map.is_shadow_free());
+    }
+
+    #[test]
+    #[should_panic(expected = "shadowed keys found: [\"x\"]")]
+    fn assert_no_shadowing() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        chain_map.assert_no_shadowing();
+    }
+
+    #[test]
+    fn map_keys() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        chain_map.insert("X", 1);
+        let lowered = chain_map.map_keys(|k| k.to_lowercase());
+        assert_eq!(lowered.depth(), 2);
+        assert_eq!(lowered.get("x"), Some(&1));
+        assert_eq!(lowered.maps[0].get("x"), Some(&0));
+    }
+
+    #[test]
+    fn map_layers() {
+        let mut chain_map = ChainMap::default();
+        chain_map.new_child();
+        chain_map.new_child();
+        let mut calls = 0;
+        chain_map.map_layers(|map| {
+            map.insert("layer", calls);
+            calls += 1;
+        });
+        assert_eq!(calls, chain_map.depth());
+        assert_eq!(chain_map.maps[0].get("layer"), Some(&0));
+        assert_eq!(chain_map.get("layer"), Some(&2));
+    }
+
+    #[test]
+    fn collect_keys() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.insert("y", 2);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        let keys = chain_map.collect_keys();
+        assert_eq!(keys.len(), 2);
+        assert!(keys.contains(&"x"));
+        assert!(keys.contains(&"y"));
+    }
+
+    #[test]
+    fn values() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.insert("y", 2);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        let mut values: Vec<_> = chain_map.values().collect();
+        values.sort();
+        assert_eq!(values, vec![&1, &2]);
+    }
+
+    #[test]
+    fn values_cloned() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", String::from("outer"));
+        chain_map.new_child();
+        chain_map.insert("x", String::from("inner"));
+        chain_map.insert("y", String::from("y"));
+        let mut values: Vec<String> = chain_map.values_cloned().collect();
+        values.sort();
+        assert_eq!(values.len(), chain_map.len());
+        assert_eq!(values, vec!["inner", "y"]);
+    }
+
+    #[test]
+    fn layer_eq() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        assert!(chain_map.layer_eq(0, 1));
+        assert!(!chain_map.layer_eq(1, 2));
+    }
+
+    #[test]
+    fn apply() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        chain_map.insert("y", 1);
+        assert_eq!(chain_map.apply(|top| top.len()), 1);
+        assert!(!chain_map.apply(|top| top.contains_key("x")));
+    }
+
+    #[test]
+    fn apply_mut() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        let old = chain_map.apply_mut(|top| top.insert("x", 1));
+        assert_eq!(old, None);
+        assert_eq!(chain_map.maps[1].get("x"), Some(&1));
+        assert_eq!(chain_map.maps[0].get("x"), Some(&0));
+    }
+
+    #[test]
+    fn from_fn() {
+        let mut calls = Vec::new();
+        let chain_map = ChainMap::from_fn(3, |i| {
+            calls.push(i);
+            let mut map = HashMap::new();
+            map.insert("layer", i);
+            map
+        });
+        assert_eq!(calls, vec![0, 1, 2]);
+        assert_eq!(chain_map.depth(), 3);
+        for i in 0..3 {
+            assert_eq!(chain_map.maps[i].get("layer"), Some(&i));
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn from_fn_zero_depth() {
+        let _: ChainMap<(), ()> = ChainMap::from_fn(0, |_| HashMap::new());
+    }
+
+    #[test]
+    fn check_invariants() {
+        let mut chain_map: ChainMap<&str, ()> = ChainMap::default();
+        assert_eq!(chain_map.check_invariants(), Ok(()));
+        chain_map.assert_invariants();
+        chain_map.maps.clear();
+        assert_eq!(
+            chain_map.check_invariants(),
+            Err(InvariantViolation::EmptyLayerVec)
+        );
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "chain map has no layers")]
+    fn assert_invariants() {
+        let chain_map: ChainMap<&str, ()> = ChainMap { maps: Vec::new() };
+        chain_map.assert_invariants();
+    }
+
+    #[test]
+    fn subtree() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("builtin", 0);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        chain_map.new_child();
+        chain_map.insert("y", 2);
+        let user = chain_map.subtree(1);
+        assert_eq!(user.depth(), 2);
+        assert_eq!(user.get("x"), Some(&1));
+        assert!(!user.contains_key("builtin"));
+        assert_eq!(chain_map.subtree(2).len(), 1);
+    }
+
+    #[test]
+    #[should_panic]
+    fn subtree_out_of_bounds() {
+        let chain_map: ChainMap<&str, ()> = ChainMap::default();
+        chain_map.subtree(1);
+    }
+
+    #[test]
+    fn into_child() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        let mut chain_map = chain_map.into_child();
+        assert_eq!(chain_map.depth(), 2);
+        chain_map.insert("x", 1);
+        assert_eq!(chain_map.get("x"), Some(&1));
+        assert_eq!(chain_map.maps[0].get("x"), Some(&0));
+    }
+
+    #[test]
+    fn with_insert() {
+        let chain_map = ChainMap::default()
+            .with_insert("a", 1)
+            .into_child()
+            .with_insert("b", 2)
+            .with_insert("a", 3);
+        assert_eq!(chain_map.depth(), 2);
+        assert_eq!(chain_map.get("a"), Some(&3));
+        assert_eq!(chain_map.get("b"), Some(&2));
+        assert_eq!(chain_map.maps[0].get("a"), Some(&1));
+    }
+
+    #[test]
+    fn first_match() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", "1");
+        chain_map.new_child();
+        chain_map.insert("x", "one");
+        chain_map.new_child();
+        let is_int = |v: &&str| v.parse::<i32>().is_ok();
+        assert_eq!(chain_map.first_match("x", is_int), Some(&"1"));
+        assert_eq!(chain_map.first_match("x", |_| true), Some(&"one"));
+        assert_eq!(chain_map.first_match("y", |_| true), None);
+    }
+
+    #[test]
+    fn count_keys_by() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("_x", 0);
+        chain_map.insert("y", 0);
+        chain_map.new_child();
+        chain_map.insert("_x", 1);
+        chain_map.insert("_z", 1);
+        assert_eq!(chain_map.count_keys_by(|k| k.starts_with('_')), 2);
+        assert_eq!(chain_map.count_keys_by(|_| true), chain_map.len());
+    }
+
+    #[test]
+    fn all_layers_contain() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("print", 0);
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        chain_map.insert("print", 1);
+        assert!(chain_map.all_layers_contain("print"));
+        assert!(!chain_map.all_layers_contain("x"));
+        assert!(!chain_map.all_layers_contain("y"));
+    }
+
+    #[test]
+    fn no_layer_contains() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        assert!(chain_map.no_layer_contains("y"));
+        assert!(!chain_map.no_layer_contains("x"));
+    }
+
+    #[test]
+    fn exactly_one_layer_contains() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.insert("y", 0);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        assert!(chain_map.exactly_one_layer_contains("y"));
+        assert!(!chain_map.exactly_one_layer_contains("x"));
+        assert!(!chain_map.exactly_one_layer_contains("z"));
+    }
+
+    #[test]
+    fn copy_to_top() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", vec![0]);
+        chain_map.new_child();
+        assert!(chain_map.copy_to_top("x"));
+        chain_map.get_mut("x").unwrap().push(1);
+        assert_eq!(chain_map.get("x"), Some(&vec![0, 1]));
+        assert_eq!(chain_map.maps[0].get("x"), Some(&vec![0]));
+        assert!(!chain_map.copy_to_top("x"));
+        assert!(!chain_map.copy_to_top("y"));
+    }
+
+    #[test]
+    fn contains_key_local() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        chain_map.insert("y", 1);
+        assert!(!chain_map.contains_key_local("x"));
+        assert!(chain_map.contains_key_local("y"));
+    }
+
+    #[test]
+    fn set_if_absent() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        assert!(chain_map.set_if_absent("x", 1));
+        assert!(!chain_map.set_if_absent("x", 2));
+        assert_eq!(chain_map.get("x"), Some(&1));
+        assert_eq!(chain_map.maps[0].get("x"), Some(&0));
+    }
+
+    #[test]
+    fn visible_eq() {
+        let mut layered = ChainMap::default();
+        layered.insert("x", 0);
+        layered.insert("y", 2);
+        layered.new_child();
+        layered.insert("x", 1);
+        let flat = ChainMap::default().with_insert("x", 1).with_insert("y", 2);
+        assert!(layered.visible_eq(&flat));
+        assert!(flat.visible_eq(&layered));
+        let other = ChainMap::default().with_insert("x", 0).with_insert("y", 2);
+        assert!(!layered.visible_eq(&other));
+        let fewer = ChainMap::default().with_insert("x", 1);
+        assert!(!layered.visible_eq(&fewer));
+    }
+
+    #[test]
+    fn is_subset_of() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.insert("y", 2);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        let child = ChainMap::default().with_insert("x", 1);
+        assert!(child.is_subset_of(&chain_map));
+        assert!(chain_map.is_superset_of(&child));
+        assert!(!chain_map.is_subset_of(&child));
+        let conflict = ChainMap::default().with_insert("x", 0);
+        assert!(!conflict.is_subset_of(&chain_map));
+    }
+
+    #[test]
+    fn take_top() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        let top = chain_map.take_top();
+        assert_eq!(top.depth(), 1);
+        assert_eq!(top.get("x"), Some(&1));
+        assert_eq!(chain_map.depth(), 1);
+        assert_eq!(chain_map.get("x"), Some(&0));
+        let base = chain_map.take_top();
+        assert_eq!(base.get("x"), Some(&0));
+        assert_eq!(chain_map.depth(), 1);
+        assert!(chain_map.is_empty());
+    }
+
+    #[test]
+    fn merge_into_base() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.insert("y", 0);
+        chain_map.new_child();
+        chain_map.insert("z", 1);
+        chain_map.new_child();
+        chain_map.insert("x", 2);
+        chain_map.merge_into_base();
+        assert_eq!(chain_map.depth(), 2);
+        assert_eq!(chain_map.maps[0].get("x"), Some(&2));
+        assert_eq!(chain_map.maps[0].get("y"), Some(&0));
+        assert_eq!(chain_map.maps[0].get("z"), None);
+        chain_map.merge_into_base();
+        chain_map.merge_into_base();
+        assert_eq!(chain_map.depth(), 1);
+        assert_eq!(chain_map.len(), 3);
+    }
+
+    #[test]
+    fn keys_only_in() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.insert("y", 0);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        chain_map.insert("z", 1);
+        assert_eq!(chain_map.keys_only_in(0).collect::<Vec<_>>(), vec![&"y"]);
+        assert_eq!(chain_map.keys_only_in(1).collect::<Vec<_>>(), vec![&"z"]);
+    }
+
+    #[test]
+    fn apply_to_all() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert(1, 10);
+        chain_map.new_child();
+        chain_map.insert(2, 20);
+        chain_map.insert(1, 30);
+        let remapped = chain_map.apply_to_all(|k, v| (k * 100, v + 1));
+        assert_eq!(remapped.depth(), 2);
+        assert_eq!(remapped.maps[0].get(&100), Some(&11));
+        assert_eq!(remapped.get(&100), Some(&31));
+        assert_eq!(remapped.get(&200), Some(&21));
+        assert_eq!(remapped.get(&1), None);
+    }
+
+    #[test]
+    fn trace_get() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        chain_map.insert("y", 1);
+        chain_map.new_child();
+        chain_map.insert("x", 2);
+        assert_eq!(chain_map.trace_get("x"), vec![(2, &2), (0, &0)]);
+        assert_eq!(chain_map.trace_get("y"), vec![(1, &1)]);
+        assert!(chain_map.trace_get("z").is_empty());
+    }
+
+    #[test]
+    fn into_hash_map() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.insert("y", 2);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        let map: HashMap<_, _> = chain_map.into();
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.get("x"), Some(&1));
+        assert_eq!(map.get("y"), Some(&2));
+    }
+
+    #[test]
+    fn new_with_maps() {
+        let mut base = HashMap::new();
+        base.insert("x", 0);
+        let mut top = HashMap::new();
+        top.insert("x", 1);
+        let chain_map = ChainMap::new_with_maps(vec![base.clone()]).unwrap();
+        assert_eq!(chain_map.depth(), 1);
+        let chain_map = ChainMap::new_with_maps(vec![base, top]).unwrap();
+        assert_eq!(chain_map.depth(), 2);
+        assert_eq!(chain_map.get("x"), Some(&1));
+        assert!(ChainMap::<&str, i32>::new_with_maps(Vec::new()).is_none());
+    }
+
+    #[test]
+    fn layer_iter() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        let layers: Vec<_> = chain_map.layer_iter().map(|map| map["x"]).collect();
+        assert_eq!(layers, vec![0, 1]);
+    }
+
+    #[test]
+    fn rev_layer_iter() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        assert_eq!(chain_map.rev_layer_iter().next(), Some(chain_map.top()));
+        let layers: Vec<_> = chain_map.rev_layer_iter().map(|map| map["x"]).collect();
+        assert_eq!(layers, vec![1, 0]);
+    }
+
+    #[test]
+    fn position_all() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        chain_map.new_child();
+        chain_map.insert("x", 2);
+        assert_eq!(chain_map.position_all("x"), vec![2, 0]);
+        assert!(chain_map.position_all("y").is_empty());
+    }
+
+    #[test]
+    fn chain_len() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.insert("y", 0);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        assert_eq!(chain_map.chain_len(), 3);
+        assert_eq!(chain_map.len(), 2);
+        assert_eq!(chain_map.local_len(), 1);
+    }
+
+    #[test]
+    fn insert_many() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        chain_map.insert("y", 0);
+        let added = chain_map.insert_many(vec![("x", 1), ("y", 1), ("z", 1)]);
+        assert_eq!(added, 2);
+        assert_eq!(chain_map.local_len(), 3);
+        assert_eq!(chain_map.get("y"), Some(&1));
+        assert_eq!(chain_map.maps[0].get("x"), Some(&0));
+    }
+
+    #[test]
+    fn drain_layer_at() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        let drained: Vec<_> = chain_map.drain_layer_at(0).collect();
+        assert_eq!(drained, vec![("x", 0)]);
+        assert_eq!(chain_map.depth(), 2);
+        assert!(chain_map.maps[0].is_empty());
+        assert_eq!(chain_map.get("x"), Some(&1));
+    }
+
+    #[test]
+    fn swap_top_with() {
+        let mut chain_map: ChainMap<String, i32> = ChainMap::default();
+        chain_map.insert("x".to_string(), 0);
+        chain_map.new_child();
+        assert_eq!(chain_map.swap_top_with("x", 1), None);
+        assert_eq!(chain_map.swap_top_with("x", 2), Some(1));
+        assert_eq!(chain_map.get("x"), Some(&2));
+        assert_eq!(chain_map.maps[0].get("x"), Some(&0));
+    }
+
+    #[test]
+    fn get_or_else() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        assert_eq!(chain_map.get_or_else("x", || unreachable!()), 0);
+        assert_eq!(chain_map.get_or_else("y", || 5), 5);
+        assert!(!chain_map.contains_key("y"));
+    }
+
+    #[test]
+    fn extend_layers() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        let layers: Vec<HashMap<_, _>> = (1..=2)
+            .map(|i| vec![("x", i)].into_iter().collect())
+            .collect();
+        chain_map.extend(layers);
+        assert_eq!(chain_map.depth(), 3);
+        assert_eq!(chain_map.maps[1].get("x"), Some(&1));
+        assert_eq!(chain_map.get("x"), Some(&2));
+    }
+
+    #[test]
+    fn retain_visible() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.insert("y", 2);
+        chain_map.insert("z", 4);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        chain_map.insert("y", 3);
+        let mut calls = 0;
+        chain_map.retain_visible(|_, v| {
+            calls += 1;
+            *v += 10;
+            *v != 13
+        });
+        assert_eq!(calls, 3);
+        assert_eq!(chain_map.get("x"), Some(&11));
+        assert_eq!(chain_map.get("y"), None);
+        assert_eq!(chain_map.get("z"), Some(&14));
+        assert_eq!(chain_map.chain_len(), 2);
+    }
+
+    #[test]
+    fn iter_pairs_mut() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.insert("y", 2);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        let mut visited: Vec<_> = chain_map
+            .iter_pairs_mut()
+            .map(|(k, v)| {
+                *v *= 10;
+                *k
+            })
+            .collect();
+        visited.sort();
+        assert_eq!(visited, vec!["x", "y"]);
+        assert_eq!(chain_map.get("x"), Some(&10));
+        assert_eq!(chain_map.get("y"), Some(&20));
+        assert_eq!(chain_map.maps[0].get("x"), Some(&0));
+    }
+
+    #[test]
+    fn get_local() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        chain_map.insert("y", 1);
+        assert_eq!(chain_map.get_local("x"), None);
+        assert_eq!(chain_map.get_local("y"), Some(&1));
+    }
+
+    #[test]
+    fn get_mut_local() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        assert_eq!(chain_map.get_mut_local("x"), None);
+        chain_map.insert("x", 1);
+        *chain_map.get_mut_local("x").unwrap() += 1;
+        assert_eq!(chain_map.get("x"), Some(&2));
+        assert_eq!(chain_map.maps[0].get("x"), Some(&0));
+    }
+
+    #[test]
+    fn contains_value() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.insert("y", 2);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        assert!(chain_map.contains_value(&1));
+        assert!(chain_map.contains_value(&2));
+        assert!(!chain_map.contains_value(&0));
+    }
+
+    #[test]
+    fn key_of_value() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.insert("y", 2);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        assert_eq!(chain_map.key_of_value(&2), Some(&"y"));
+        assert_eq!(chain_map.key_of_value(&1), Some(&"x"));
+        assert_eq!(chain_map.key_of_value(&0), None);
+    }
+
+    #[test]
+    fn sum() {
+        let empty: ChainMap<&str, i32> = Vec::new().into_iter().sum();
+        assert_eq!(empty.depth(), 1);
+        assert!(empty.is_empty());
+        let one: ChainMap<_, _> = vec![ChainMap::default().with_insert("x", 0)]
+            .into_iter()
+            .sum();
+        assert_eq!(one.depth(), 1);
+        assert_eq!(one.get("x"), Some(&0));
+        let chains = vec![
+            ChainMap::default().with_insert("x", 0),
+            ChainMap::default()
+                .with_insert("x", 1)
+                .into_child()
+                .with_insert("y", 2),
+        ];
+        let many: ChainMap<_, _> = chains.into_iter().sum();
+        assert_eq!(many.depth(), 3);
+        assert_eq!(many.maps[0].get("x"), Some(&0));
+        assert_eq!(many.get("x"), Some(&1));
+    }
+
+    #[test]
+    fn find_layer() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        chain_map.new_child();
+        chain_map.insert("y", 2);
+        let (index, layer) = chain_map.find_layer(|map| map.contains_key("x")).unwrap();
+        assert_eq!(index, 1);
+        assert_eq!(layer.get("x"), Some(&1));
+        assert!(chain_map.find_layer(|map| map.len() > 1).is_none());
+    }
+
+    #[test]
+    fn find_layer_mut() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        let (index, layer) = chain_map.find_layer_mut(|map| !map.is_empty()).unwrap();
+        assert_eq!(index, 0);
+        layer.insert("y", 1);
+        assert_eq!(chain_map.maps[0].get("y"), Some(&1));
+    }
+
+    #[test]
+    fn clone_top() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        chain_map.insert("y", 1);
+        let mut top = chain_map.clone_top();
+        assert_eq!(top.len(), 1);
+        assert_eq!(top.get("y"), Some(&1));
+        top.insert("z", 2);
+        assert!(!chain_map.contains_key("z"));
+    }
+
+    #[test]
+    fn clone_base() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        chain_map.insert("y", 1);
+        let base = chain_map.clone_base();
+        assert_eq!(base.len(), 1);
+        assert_eq!(base.get("x"), Some(&0));
+    }
+
+    #[test]
+    fn copy_layer_to_top() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        chain_map.copy_layer_to_top(0);
+        assert_eq!(chain_map.depth(), 3);
+        assert!(chain_map.layer_eq(0, 2));
+        assert_eq!(chain_map.get("x"), Some(&0));
+    }
+
+    #[test]
+    fn layer_intersection() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.insert("y", 0);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        chain_map.insert("z", 1);
+        let common = chain_map.layer_intersection(0, 1);
+        assert_eq!(common.len(), 1);
+        assert_eq!(common.get(&"x"), Some(&(&0, &1)));
+    }
+
+    #[test]
+    fn layer_difference() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.insert("y", 0);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        chain_map.insert("z", 1);
+        let removed = chain_map.layer_difference(0, 1);
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed.get(&"y"), Some(&&0));
+        let added = chain_map.layer_difference(1, 0);
+        assert_eq!(added.len(), 1);
+        assert_eq!(added.get(&"z"), Some(&&1));
+    }
+
+    #[test]
+    fn into_iter() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        let mut values = Vec::new();
+        for layer in chain_map {
+            values.push(layer["x"]);
+        }
+        assert_eq!(values, vec![0, 1]);
+    }
+
+    #[test]
+    fn into_entries() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        let entries: Vec<_> = chain_map.into_entries().collect();
+        assert_eq!(entries, vec![("x", 0), ("x", 1)]);
+    }
+
+    #[test]
+    fn apply_to_base() {
+        let mut chain_map = ChainMap::default();
+        chain_map.new_child();
+        chain_map.apply_to_base(|base| {
+            base.insert("print", 0);
+            base.insert("len", 1);
+        });
+        assert_eq!(chain_map.maps[0].len(), 2);
+        assert!(chain_map.top().is_empty());
+    }
+
+    #[test]
+    fn prepend_layer() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 1);
+        let mut fallback = HashMap::new();
+        fallback.insert("x", 0);
+        fallback.insert("y", 0);
+        chain_map.prepend_layer(fallback);
+        assert_eq!(chain_map.depth(), 2);
+        assert_eq!(chain_map.get("x"), Some(&1));
+        assert_eq!(chain_map.get("y"), Some(&0));
+        chain_map.insert("z", 1);
+        assert_eq!(chain_map.maps[1].get("z"), Some(&1));
+    }
+
+    #[test]
+    fn append_chain() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        let other = ChainMap::default()
+            .with_insert("x", 1)
+            .into_child()
+            .with_insert("y", 2);
+        chain_map.append_chain(other);
+        assert_eq!(chain_map.depth(), 3);
+        assert_eq!(chain_map.maps[1].get("x"), Some(&1));
+        assert_eq!(chain_map.get("y"), Some(&2));
+    }
+
+    #[test]
+    fn extend_from_chain() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        let other = ChainMap::default().with_insert("x", 1);
+        chain_map.extend_from_chain(&other);
+        assert_eq!(chain_map.depth(), 2);
+        assert_eq!(chain_map.get("x"), Some(&1));
+        assert_eq!(other.get("x"), Some(&1));
+    }
+
+    #[test]
+    fn into_scoped() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        let inner = chain_map.into_scoped(|scope| {
+            scope.insert("x", 1);
+            assert_eq!(scope.depth(), 2);
+            *scope.get("x").unwrap()
+        });
+        assert_eq!(inner, 1);
+        assert_eq!(chain_map.depth(), 1);
+        assert_eq!(chain_map.get("x"), Some(&0));
+    }
+
+    #[test]
+    fn into_scoped_panic() {
+        use std::panic::{catch_unwind, AssertUnwindSafe};
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        let result = catch_unwind(AssertUnwindSafe(|| {
+            chain_map.into_scoped(|scope| {
+                scope.insert("x", 1);
+                scope.new_child();
+                panic!("scoped panic");
+            })
+        }));
+        assert!(result.is_err());
+        assert_eq!(chain_map.depth(), 1);
+        assert_eq!(chain_map.get("x"), Some(&0));
+    }
+
+    #[test]
+    fn into_scoped_pops_parent() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        chain_map.into_scoped(|scope| {
+            scope.remove_child();
+            scope.remove_child();
+        });
+        assert_eq!(chain_map.depth(), 1);
+        assert_eq!(chain_map.get("x"), Some(&0));
+    }
+
+    #[test]
+    fn all_visible_values() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.insert("y", 2);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        let sum: i32 = chain_map.all_visible_values().sum();
+        assert_eq!(sum, 3);
+    }
+
+    #[test]
+    fn write_to() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("y", 2);
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        chain_map.new_child();
+        let mut out = Vec::new();
+        chain_map.write_to(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "Layer 0:\n  x: 0\n  y: 2\nLayer 1:\n  x: 1\nLayer 2:\n"
+        );
+    }
+
+    #[test]
+    fn assert_contains() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        chain_map.assert_contains("x");
+        chain_map.assert_not_contains("y");
+    }
+
+    #[test]
+    #[should_panic(expected = "key \"y\" not found in any of 2 layers")]
+    fn assert_contains_missing() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        chain_map.assert_contains("y");
+    }
+
+    #[test]
+    #[should_panic(expected = "key \"x\" found in layers [1, 0]")]
+    fn assert_not_contains_present() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        chain_map.assert_not_contains("x");
+    }
+
+    #[test]
+    fn map_layer() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.insert("y", 5);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        chain_map.map_layer(0, |layer| {
+            layer.into_iter().filter(|(_, v)| *v > 0).collect()
+        });
+        assert_eq!(chain_map.depth(), 2);
+        assert_eq!(chain_map.maps[0].len(), 1);
+        assert_eq!(chain_map.get("y"), Some(&5));
+        assert_eq!(chain_map.get("x"), Some(&1));
+    }
+
+    #[test]
+    fn count_unique_values() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.insert("y", 1);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        chain_map.insert("z", 2);
+        assert_eq!(chain_map.count_unique_values(), 2);
+    }
+
+    #[test]
+    fn find_value() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.insert("y", 1);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        let mut keys = chain_map.find_value(&1);
+        keys.sort();
+        assert_eq!(keys, vec![&"x", &"y"]);
+        assert!(chain_map.find_value(&0).is_empty());
+    }
+
+    #[test]
+    fn group_by_layer() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        chain_map.new_child();
+        chain_map.insert("x", 2);
+        let groups = chain_map.group_by_layer();
+        assert_eq!(groups.len(), 3);
+        assert_eq!(groups[&0], vec![(&"x", &0)]);
+        assert!(groups[&1].is_empty());
+        assert_eq!(groups[&2], vec![(&"x", &2)]);
+    }
+
+    #[test]
+    fn is_consistent() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        assert!(chain_map.is_consistent());
+        crate::debug_assert_consistent!(chain_map);
+        chain_map.maps.clear();
+        assert!(!chain_map.is_consistent());
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    fn debug_assert_consistent_evaluates_once() {
+        let mut chain_map: ChainMap<&str, i32> = ChainMap::default();
+        chain_map.maps.clear();
+        let mut calls = 0;
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            crate::debug_assert_consistent!({
+                calls += 1;
+                &chain_map
+            })
+        }));
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn highest_layer_with() {
+        let mut chain_map = ChainMap::default();
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        chain_map.new_child();
+        chain_map.insert("x", 2);
+        chain_map.new_child();
+        assert_eq!(chain_map.highest_layer_with("x"), Some(2));
+        assert_eq!(chain_map.lowest_layer_with("x"), Some(1));
+        assert_eq!(chain_map.highest_layer_with("y"), None);
+        assert_eq!(chain_map.lowest_layer_with("y"), None);
+    }
+
+    #[test]
+    fn take_while_layers() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("boundary", 0);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        chain_map.new_child();
+        chain_map.insert("y", 2);
+        let taken = chain_map.take_while_layers(|map| !map.contains_key("boundary"));
+        assert_eq!(taken.len(), 2);
+        assert!(taken[0].contains_key("x"));
+        assert!(taken[1].contains_key("y"));
+        assert_eq!(chain_map.depth(), 1);
+        let taken = chain_map.take_while_layers(|_| true);
+        assert!(taken.is_empty());
+        assert_eq!(chain_map.get("boundary"), Some(&0));
+    }
+
+    #[test]
+    fn get_base() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        chain_map.insert("y", 1);
+        assert_eq!(chain_map.get_base("x"), Some(&0));
+        assert_eq!(chain_map.skip_to_base_get("x"), Some(&0));
+        assert_eq!(chain_map.get_base("y"), None);
+        *chain_map.get_base_mut("x").unwrap() = 5;
+        assert_eq!(chain_map.get("x"), Some(&1));
+        chain_map.remove_child();
+        assert_eq!(chain_map.get("x"), Some(&5));
+    }
+
+    #[test]
+    fn get_or_default_insert() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 1);
+        chain_map.new_child();
+        *chain_map.get_or_default_insert("x") += 2;
+        assert_eq!(chain_map.get("x"), Some(&2));
+        *chain_map.get_or_default_insert("x") += 2;
+        assert_eq!(chain_map.get("x"), Some(&4));
+        chain_map.remove_child();
+        assert_eq!(chain_map.get("x"), Some(&1));
+    }
+
+    #[test]
+    fn flat_iter_with_layers() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        let entries: Vec<_> = chain_map.flat_iter_with_layers().collect();
+        assert_eq!(entries, vec![(0, &"x", &0), (1, &"x", &1)]);
+    }
+
+    #[test]
+    fn reverse_layers() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.insert("y", 0);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        chain_map.reverse_layers();
+        assert_eq!(chain_map.get("x"), Some(&0));
+        assert_eq!(chain_map.get_local("y"), Some(&0));
+        assert_eq!(chain_map.get_base("x"), Some(&1));
+    }
+
+    #[test]
+    fn window_get() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.insert("y", 0);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        chain_map.new_child();
+        chain_map.insert("x", 2);
+        assert_eq!(chain_map.window_get("x", 0, 1), Some(&1));
+        assert_eq!(chain_map.window_get("x", 0, 2), Some(&2));
+        assert_eq!(chain_map.window_get("y", 1, 2), None);
+        assert_eq!(chain_map.window_get("y", 0, 0), Some(&0));
+    }
+
+    #[test]
+    #[should_panic]
+    fn window_get_out_of_bounds() {
+        let chain_map: ChainMap<&str, i32> = ChainMap::default();
+        chain_map.window_get("x", 0, 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid layer range")]
+    fn window_get_reversed() {
+        let mut chain_map: ChainMap<&str, i32> = ChainMap::default();
+        chain_map.new_child();
+        chain_map.window_get("x", 1, 0);
+    }
+
+    #[test]
+    fn rename_key() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        chain_map.insert("y", 1);
+        chain_map.insert("z", 2);
+        assert!(chain_map.rename_key("y", "z"));
+        assert_eq!(chain_map.get("z"), Some(&1));
+        assert_eq!(chain_map.get("y"), None);
+        assert!(!chain_map.rename_key("x", "w"));
+        assert_eq!(chain_map.get("x"), Some(&0));
+        assert_eq!(chain_map.get("w"), None);
+    }
+
+    #[test]
+    fn is_local() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.insert("y", 0);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        chain_map.insert("z", 1);
+        assert!(chain_map.is_local("x"));
+        assert!(chain_map.is_local("z"));
+        assert!(!chain_map.is_local("y"));
+        assert!(chain_map.is_inherited("y"));
+        assert!(!chain_map.is_inherited("x"));
+        assert!(!chain_map.is_inherited("w"));
+    }
+
+    #[test]
+    fn propagate_down() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        chain_map.new_child();
+        chain_map.insert("x", 2);
+        chain_map.insert("y", 2);
+        assert!(chain_map.propagate_down("y"));
+        assert!(chain_map.propagate_down("x"));
+        chain_map.remove_child();
+        assert_eq!(chain_map.get_local("x"), Some(&2));
+        assert_eq!(chain_map.get_base("x"), Some(&2));
+        assert_eq!(chain_map.get_base("y"), Some(&2));
+        assert!(!chain_map.propagate_down("z"));
+    }
+
+    #[test]
+    fn shrink_layers_to() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        chain_map.new_child();
+        chain_map.insert("x", 2);
+        assert!(chain_map.shrink_layers_to(3).is_empty());
+        assert!(chain_map.shrink_layers_to(5).is_empty());
+        assert_eq!(chain_map.depth(), 3);
+        let removed = chain_map.shrink_layers_to(2);
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].get("x"), Some(&2));
+        assert_eq!(chain_map.get("x"), Some(&1));
+        chain_map.new_child();
+        let removed = chain_map.shrink_layers_to(1);
+        assert_eq!(removed.len(), 2);
+        assert_eq!(removed[0].get("x"), Some(&1));
+        assert_eq!(chain_map.depth(), 1);
+    }
+
+    #[test]
+    fn shrink_layers_to_zero() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        let removed = chain_map.shrink_layers_to(0);
+        assert_eq!(removed.len(), 1);
+        assert_eq!(chain_map.depth(), 1);
+        assert_eq!(chain_map.get("x"), Some(&0));
+    }
+
+    #[test]
+    fn layer_map() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 1);
+        chain_map.new_child();
+        chain_map.insert("x", 2);
+        let doubled = chain_map.layer_map(|map| map.iter().map(|(k, v)| (*k, v * 2)).collect());
+        assert_eq!(doubled.depth(), 2);
+        assert_eq!(doubled.get("x"), Some(&4));
+        assert_eq!(doubled.get_base("x"), Some(&2));
+        assert_eq!(chain_map.get("x"), Some(&2));
+    }
+
+    #[test]
+    fn zip_layers() {
+        let mut left = ChainMap::default();
+        left.insert("x", 0);
+        left.insert("y", 0);
+        left.new_child();
+        left.insert("x", 1);
+        let mut right = ChainMap::default();
+        right.insert("x", 10);
+        right.new_child();
+        right.insert("z", 11);
+        let zipped = left.zip_layers(&right).unwrap();
+        assert_eq!(zipped.depth(), 2);
+        assert_eq!(zipped.get("x"), Some(&(0, 10)));
+        assert_eq!(zipped.get("y"), None);
+        assert_eq!(zipped.get("z"), None);
+        right.new_child();
+        assert!(left.zip_layers(&right).is_none());
+    }
+
+    #[test]
+    fn enumerate_layers() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        chain_map.insert("x", 0);
+        for (i, map) in chain_map.enumerate_layers_mut() {
+            map.insert("x", i);
+        }
+        let values: Vec<_> = chain_map
+            .enumerate_layers()
+            .map(|(i, map)| (i, map["x"]))
+            .collect();
+        assert_eq!(values, vec![(0, 0), (1, 1)]);
+    }
+
+    #[test]
+    fn visible_pairs_sorted_by_key() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("c", 0);
+        chain_map.insert("a", 0);
+        chain_map.new_child();
+        chain_map.insert("b", 1);
+        chain_map.insert("a", 1);
+        let pairs = chain_map.visible_pairs_sorted_by_key();
+        assert_eq!(pairs, vec![(&"a", &1), (&"b", &1), (&"c", &0)]);
+        assert_eq!(chain_map.visible_pairs_sorted_by_key(), pairs);
+    }
+
+    #[test]
+    fn total_memory_estimate() {
+        let mut chain_map: ChainMap<u64, u64> = ChainMap::default();
+        let empty = chain_map.total_memory_estimate();
+        assert!(empty >= std::mem::size_of::<Vec<HashMap<u64, u64>>>());
+        chain_map.insert(1, 1);
+        let one = chain_map.total_memory_estimate();
+        assert!(one >= empty + 16);
+        chain_map.new_child();
+        assert!(chain_map.total_memory_estimate() > one);
+    }
+
+    #[test]
+    fn merge_adjacent_layers() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.insert("y", 0);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        chain_map.new_child();
+        chain_map.insert("x", 2);
+        chain_map.merge_adjacent_layers(0);
+        assert_eq!(chain_map.depth(), 2);
+        assert_eq!(chain_map.get_base("x"), Some(&1));
+        assert_eq!(chain_map.get_base("y"), Some(&0));
+        assert_eq!(chain_map.get("x"), Some(&2));
+    }
+
+    #[test]
+    #[should_panic]
+    fn merge_adjacent_layers_out_of_bounds() {
+        let mut chain_map: ChainMap<&str, i32> = ChainMap::default();
+        chain_map.merge_adjacent_layers(0);
+    }
+
+    #[test]
+    #[should_panic(expected = "has no layer above it")]
+    fn merge_adjacent_layers_max_index() {
+        let mut chain_map: ChainMap<&str, i32> = ChainMap::default();
+        chain_map.merge_adjacent_layers(usize::MAX);
+    }
+
+    #[test]
+    fn count_insertions_since_snapshot() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.insert("y", 0);
+        let snap = chain_map.snapshot();
+        assert_eq!(snap.top_len(), 2);
+        assert_eq!(snap.depth(), 1);
+        assert_eq!(chain_map.count_insertions_since_snapshot(&snap), Some(0));
+        chain_map.insert("z", 0);
+        chain_map.insert("x", 1);
+        assert_eq!(chain_map.count_insertions_since_snapshot(&snap), Some(1));
+        chain_map.new_child();
+        assert_eq!(chain_map.count_insertions_since_snapshot(&snap), None);
+    }
+
+    #[test]
+    fn iter_visible_in_reverse() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        chain_map.insert("y", 1);
+        chain_map.new_child();
+        chain_map.insert("y", 2);
+        let pairs: Vec<_> = chain_map.iter_visible_in_reverse().collect();
+        assert_eq!(pairs, vec![(&"x", &0), (&"y", &1)]);
+    }
+
+    #[test]
+    fn flatten_except_base() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.insert("y", 0);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        chain_map.insert("z", 1);
+        chain_map.new_child();
+        chain_map.insert("z", 2);
+        let flat = chain_map.flatten_except_base();
+        assert_eq!(flat.depth(), 2);
+        assert_eq!(flat.maps[0], chain_map.maps[0]);
+        assert_eq!(flat.maps[1], chain_map.flatten_range(1, 2));
+        assert_eq!(flat.get("z"), Some(&2));
+
+        let base_only: ChainMap<&str, i32> = ChainMap::default();
+        let flat = base_only.flatten_except_base();
+        assert_eq!(flat.depth(), 2);
+        assert!(flat.is_empty());
+    }
+
+    #[test]
+    fn iter_shadowed_pairs() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.insert("y", 0);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        chain_map.new_child();
+        chain_map.insert("x", 2);
+        let hidden: Vec<_> = chain_map.iter_shadowed_pairs().collect();
+        assert_eq!(hidden, vec![(&"x", &1, 1), (&"x", &0, 0)]);
+    }
+
+    #[test]
+    fn is_strictly_layered() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        chain_map.insert("y", 1);
+        assert!(chain_map.is_strictly_layered());
+        chain_map.insert("x", 1);
+        assert!(!chain_map.is_strictly_layered());
+        chain_map.dedup_keys();
+        assert!(chain_map.is_strictly_layered());
+    }
+
+    #[test]
+    fn compare_visible() {
+        let mut before = ChainMap::default();
+        before.insert("x", 0);
+        before.insert("y", 0);
+        before.insert("z", 0);
+        let mut after = ChainMap::default();
+        after.insert("x", 0);
+        after.insert("y", 0);
+        after.new_child();
+        after.insert("y", 1);
+        after.insert("w", 1);
+        let result = before.compare_visible(&after);
+        assert_eq!(result.added, vec![(&"w", &1)].into_iter().collect());
+        assert_eq!(result.removed, vec![(&"z", &0)].into_iter().collect());
+        assert_eq!(result.changed, vec![(&"y", (&0, &1))].into_iter().collect());
+        assert!(before.compare_visible(&before).is_empty());
+    }
+
+    #[test]
+    fn chain_from_nested() {
+        let chain_map =
+            ChainMap::chain_from_nested(vec![vec![("x", 0), ("y", 0)], vec![("x", 1)], vec![]]);
+        assert_eq!(chain_map.depth(), 3);
+        assert_eq!(chain_map.get("x"), Some(&1));
+        assert_eq!(chain_map.get("y"), Some(&0));
+
+        let empty: ChainMap<&str, i32> = ChainMap::chain_from_nested(Vec::<Vec<_>>::new());
+        assert_eq!(empty.depth(), 1);
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn insert_shadowing_only() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        assert_eq!(chain_map.insert_shadowing_only("y", 1), None);
+        assert!(!chain_map.contains_key("y"));
+        assert_eq!(chain_map.insert_shadowing_only("x", 1), None);
+        assert_eq!(chain_map.get("x"), Some(&1));
+        assert_eq!(chain_map.insert_shadowing_only("x", 2), Some(1));
+        assert_eq!(chain_map.get_base("x"), Some(&0));
+    }
+
+    #[test]
+    fn get_unchecked_top() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        assert_eq!(unsafe { chain_map.get_unchecked_top("x") }, &1);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "not in the top layer")]
+    fn get_unchecked_top_missing() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        unsafe {
+            chain_map.get_unchecked_top("x");
+        }
+    }
+
+    #[test]
+    fn from_iter_of_layers() {
+        let children = vec![vec![("x", 1)], vec![("y", 2)]];
+        let chain_map = ChainMap::from_iter_of_layers(vec![("x", 0), ("y", 0)], children);
+        assert_eq!(chain_map.depth(), 3);
+        assert_eq!(chain_map.get("x"), Some(&1));
+        assert_eq!(chain_map.get("y"), Some(&2));
+        assert_eq!(chain_map.get_base("x"), Some(&0));
+
+        let base_only = ChainMap::from_iter_of_layers(vec![("x", 0)], Vec::new());
+        assert_eq!(base_only.depth(), 1);
+    }
+
+    #[test]
+    fn index_layer() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        assert_eq!(chain_map[0]["x"], 0);
+        assert_eq!(chain_map[1]["x"], 1);
+        chain_map[0].insert("y", 0);
+        assert_eq!(chain_map["y"], 0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn index_layer_out_of_bounds() {
+        let chain_map: ChainMap<&str, i32> = ChainMap::default();
+        let _ = &chain_map[1];
+    }
+
+    #[test]
+    fn num_layers_with_key() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.insert("y", 0);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        chain_map.new_child();
+        chain_map.insert("x", 2);
+        assert_eq!(chain_map.num_layers_with_key("z"), 0);
+        assert_eq!(chain_map.num_layers_with_key("y"), 1);
+        assert_eq!(chain_map.num_layers_with_key("x"), 3);
+        assert_eq!(chain_map.count_layers_containing("x"), 3);
+    }
+
+    #[test]
+    fn from_iter() {
+        let mut base = HashMap::new();
+        base.insert("x", 0);
+        let mut top = HashMap::new();
+        top.insert("x", 1);
+        let chain_map: ChainMap<_, _> = vec![base, top].into_iter().collect();
+        assert_eq!(chain_map.depth(), 2);
+        assert_eq!(chain_map.get("x"), Some(&1));
+        assert_eq!(chain_map.get_base("x"), Some(&0));
+
+        let empty: ChainMap<&str, i32> = Vec::new().into_iter().collect();
+        assert_eq!(empty.depth(), 1);
+    }
+
+    #[test]
+    fn apply_chain_lookup() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", vec![1, 2]);
+        chain_map.new_child();
+        chain_map.insert("x", vec![1, 2, 3]);
+        assert_eq!(chain_map.apply_chain_lookup("x", Vec::len), Some(3));
+        assert_eq!(chain_map.apply_chain_lookup("y", Vec::len), None);
+    }
+
+    #[test]
+    fn make_top_authoritative() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.insert("y", 0);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        chain_map.new_child();
+        chain_map.insert("z", 2);
+        chain_map.make_top_authoritative();
+        for key in &["x", "y", "z"] {
+            assert_eq!(chain_map.get_local(key), chain_map.get(key));
+        }
+        assert_eq!(chain_map.get_local("x"), Some(&1));
+        assert_eq!(chain_map.top().len(), 3);
+    }
+
+    #[test]
+    fn value_changed_in_top() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        assert!(chain_map.value_changed_in_top("x", &0));
+        chain_map.insert("x", 1);
+        assert!(!chain_map.value_changed_in_top("x", &1));
+        assert!(chain_map.value_changed_in_top("x", &2));
+    }
+
+    #[test]
+    fn iter_local_keys_shadowing_parent() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.insert("y", 0);
+        chain_map.new_child();
+        chain_map.insert("y", 1);
+        chain_map.new_child();
+        chain_map.insert("x", 2);
+        chain_map.insert("z", 2);
+        let keys: Vec<_> = chain_map.iter_local_keys_shadowing_parent().collect();
+        assert_eq!(keys, vec![&"x"]);
+        chain_map.remove_child();
+        chain_map.remove_child();
+        assert_eq!(chain_map.iter_local_keys_shadowing_parent().count(), 0);
+    }
+
+    #[test]
+    fn absorb_parent() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("w", 0);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        chain_map.insert("y", 1);
+        chain_map.new_child();
+        chain_map.insert("x", 2);
+        chain_map.absorb_parent();
+        assert_eq!(chain_map.depth(), 2);
+        assert_eq!(chain_map.get_local("x"), Some(&2));
+        assert_eq!(chain_map.get_local("y"), Some(&1));
+        assert_eq!(chain_map.get_local("w"), None);
+        chain_map.absorb_parent();
+        chain_map.absorb_parent();
+        assert_eq!(chain_map.depth(), 1);
+        assert_eq!(chain_map.len(), 3);
+    }
+
+    #[test]
+    fn peek_parent() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        assert!(chain_map.peek_parent().is_none());
+        assert!(chain_map.peek_parent_mut().is_none());
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        chain_map.new_child();
+        assert_eq!(chain_map.peek_parent().unwrap().get("x"), Some(&1));
+        chain_map.peek_parent_mut().unwrap().insert("y", 1);
+        assert_eq!(chain_map.get("y"), Some(&1));
+        assert_eq!(chain_map.get_base("y"), None);
+    }
+
+    #[test]
+    fn transfer_to_parent() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        assert!(!chain_map.transfer_to_parent("x"));
+        assert_eq!(chain_map.get("x"), Some(&0));
+        chain_map.new_child();
+        chain_map.new_child();
+        chain_map.insert("x", 2);
+        assert!(chain_map.transfer_to_parent("x"));
+        assert!(!chain_map.transfer_to_parent("x"));
+        assert_eq!(chain_map.get_local("x"), None);
+        assert_eq!(chain_map.peek_parent().unwrap().get("x"), Some(&2));
+        assert_eq!(chain_map.get_base("x"), Some(&0));
+    }
+
+    #[test]
+    fn intersect_with_map() {
+        let mut builtins = HashMap::new();
+        builtins.insert("print", 0);
+        builtins.insert("len", 0);
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("print", 1);
+        chain_map.insert("x", 1);
+        chain_map.new_child();
+        chain_map.insert("print", 2);
+        let overridden = chain_map.intersect_with_map(&builtins);
+        assert_eq!(overridden.len(), 1);
+        assert_eq!(overridden[&"print"], (&2, &0));
+    }
+
+    #[test]
+    fn subtract_map() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        chain_map.insert("y", 1);
+        chain_map.insert("z", 1);
+        let keys: HashSet<_> = vec!["x", "y", "w"].into_iter().collect();
+        assert_eq!(chain_map.subtract_map(&keys), 2);
+        assert_eq!(chain_map.get("x"), Some(&0));
+        assert_eq!(chain_map.get("y"), None);
+        assert_eq!(chain_map.get("z"), Some(&1));
+        assert_eq!(chain_map.subtract_map(&keys), 0);
+    }
+
+    #[test]
+    fn try_get_many_mut() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.insert("y", 0);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        chain_map.insert("z", 2);
+        let [x, y, z] = chain_map.try_get_many_mut(["x", "y", "z"]).unwrap();
+        std::mem::swap(x, y);
+        *z += 1;
+        assert_eq!(chain_map.get("x"), Some(&0));
+        assert_eq!(chain_map.get("y"), Some(&1));
+        assert_eq!(chain_map.get("z"), Some(&3));
+        assert_eq!(
+            chain_map.try_get_many_mut(["x", "y", "x"]),
+            Err(DuplicateKeyError::Duplicate)
+        );
+        assert_eq!(
+            chain_map.try_get_many_mut(["x", "w"]),
+            Err(DuplicateKeyError::Missing { index: 1 })
+        );
+    }
+
+    #[test]
+    fn depth_search() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 4);
+        chain_map.new_child();
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        let even = chain_map.depth_search("x", |i, v| if v % 2 == 0 { Some(i) } else { None });
+        assert_eq!(even, Some(0));
+        assert_eq!(chain_map.depth_search("x", |i, _| Some(i)), Some(2));
+        assert_eq!(chain_map.depth_search("y", |i, _| Some(i)), None);
+    }
+
+    #[test]
+    fn layer_union() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.insert("y", 0);
+        chain_map.new_child();
+        chain_map.insert("w", 1);
+        chain_map.new_child();
+        chain_map.insert("x", 2);
+        chain_map.insert("z", 2);
+        let union = chain_map.layer_union(0, 2);
+        assert_eq!(union.len(), 3);
+        assert_eq!(union["x"], &2);
+        assert_eq!(union["y"], &0);
+        assert_eq!(union["z"], &2);
+        assert_eq!(chain_map.layer_union(2, 0)["x"], &0);
+    }
+
+    #[test]
+    #[should_panic]
+    fn layer_union_out_of_bounds() {
+        let chain_map: ChainMap<&str, i32> = ChainMap::default();
+        chain_map.layer_union(0, 1);
+    }
+
+    #[test]
+    fn iter_child_layers() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        assert_eq!(chain_map.iter_child_layers().count(), 0);
+        chain_map.new_child();
+        chain_map.new_child();
+        for map in chain_map.iter_child_layers_mut() {
+            map.insert("x", 1);
+        }
+        assert_eq!(chain_map.iter_child_layers().count(), 2);
+        assert!(chain_map.iter_child_layers().all(|map| map["x"] == 1));
+        assert_eq!(chain_map.get_base("x"), Some(&0));
+    }
+
+    #[test]
+    fn clear_non_base_layers() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        assert!(chain_map.local_is_empty());
+        chain_map.insert("y", 1);
+        assert!(!chain_map.local_is_empty());
+        chain_map.clear_non_base_layers();
+        assert_eq!(chain_map.depth(), 1);
+        assert!(chain_map.local_is_empty());
+        assert!(chain_map.is_empty());
+        assert!(chain_map.maps[0].capacity() > 0);
+    }
+
+    #[test]
+    fn fold_layers() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.insert("y", 0);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        let total = chain_map.fold_layers(0, |acc, map| acc + map.len());
+        assert_eq!(total, 3);
+        let order = chain_map.fold_layers(Vec::new(), |mut acc, map| {
+            acc.push(map["x"]);
+            acc
+        });
+        assert_eq!(order, vec![0, 1]);
+    }
+
+    #[test]
+    fn global_retain() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.insert("y", 0);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        let mut calls = 0;
+        chain_map.global_retain(|k, _| {
+            calls += 1;
+            *k != "x"
+        });
+        assert_eq!(calls, 3);
+        assert!(!chain_map.contains_key("x"));
+        assert_eq!(chain_map.get("y"), Some(&0));
+        assert_eq!(chain_map.depth(), 2);
+    }
+
+    #[test]
+    fn count_visible_by() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 10);
+        chain_map.insert("y", 6);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        assert_eq!(chain_map.count_visible_by(|_, v| *v > 5), 1);
+        assert_eq!(chain_map.count_visible_by(|k, v| *k == "x" && *v == 1), 1);
+    }
+
+    #[test]
+    fn first_visible() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        chain_map.insert("y", 1);
+        assert_eq!(chain_map.first_visible(|_, _| true), Some((&"y", &1)));
+        assert_eq!(chain_map.last_visible(|_, _| true), Some((&"x", &0)));
+        assert_eq!(chain_map.first_visible(|_, v| *v > 1), None);
+        assert_eq!(chain_map.last_visible(|_, v| *v > 1), None);
+    }
+
+    #[test]
+    fn insert_batch_into_layer() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        chain_map.new_child();
+        chain_map.insert_batch_into_layer(1, vec![("x", 1), ("y", 1), ("y", 2)]);
+        assert_eq!(chain_map[1].len(), 2);
+        assert_eq!(chain_map[1]["y"], 2);
+        assert!(chain_map.local_is_empty());
+        assert_eq!(chain_map.get("x"), Some(&1));
+        assert_eq!(chain_map.get_base("x"), Some(&0));
+    }
+
+    #[test]
+    #[should_panic]
+    fn insert_batch_into_layer_out_of_bounds() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert_batch_into_layer(1, vec![("x", 0)]);
+    }
+
+    #[test]
+    fn keys_added_in_top() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        chain_map.insert("y", 1);
+        let keys: Vec<_> = chain_map.keys_added_in_top().collect();
+        assert_eq!(keys, vec![&"y"]);
+    }
+
+    #[test]
+    fn shadow_depth() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.insert("y", 0);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        chain_map.new_child();
+        chain_map.insert("x", 2);
+        assert_eq!(chain_map.shadow_depth("x"), 2);
+        assert_eq!(chain_map.shadow_depth("y"), 0);
+        assert_eq!(chain_map.shadow_depth("z"), 0);
+    }
+
+    #[test]
+    fn rotate_top_to_base() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        chain_map.new_child();
+        chain_map.insert("x", 2);
+        chain_map.rotate_top_to_base();
+        assert_eq!(chain_map.depth(), 3);
+        assert_eq!(chain_map.base().get("x"), Some(&2));
+        assert_eq!(chain_map.get("x"), Some(&1));
+    }
+
+    #[test]
+    fn rotate_base_to_top() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.rotate_base_to_top();
+        assert_eq!(chain_map.get("x"), Some(&0));
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        chain_map.new_child();
+        chain_map.rotate_base_to_top();
+        assert_eq!(chain_map.depth(), 3);
+        assert_eq!(chain_map.get_local("x"), Some(&0));
+        assert_eq!(chain_map.base().get("x"), Some(&1));
+        chain_map.rotate_top_to_base();
+        assert_eq!(chain_map.get_base("x"), Some(&0));
+    }
+
+    #[test]
+    fn as_ref_slice() {
+        fn total_len<M: AsRef<[HashMap<&'static str, i32>]>>(maps: &M) -> usize {
+            maps.as_ref().iter().map(HashMap::len).sum()
+        }
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        assert_eq!(total_len(&chain_map), 2);
+        let layers: &[HashMap<_, _>] = chain_map.as_ref();
+        assert_eq!(layers.len(), 2);
+        let layers: &mut [HashMap<_, _>] = chain_map.as_mut();
+        layers[0].insert("y", 0);
+        assert_eq!(chain_map.get("y"), Some(&0));
+    }
+
+    #[test]
+    fn clone_range() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        chain_map.new_child();
+        chain_map.insert("x", 2);
+        let mut range = chain_map.clone_range(0, 1);
+        assert_eq!(range.depth(), 2);
+        assert_eq!(range.get("x"), Some(&1));
+        range.insert("x", 5);
+        assert_eq!(chain_map[1]["x"], 1);
+        assert_eq!(chain_map.clone_range(2, 2).get("x"), Some(&2));
+    }
+
+    #[test]
+    #[should_panic]
+    fn clone_range_out_of_bounds() {
+        let chain_map: ChainMap<&str, i32> = ChainMap::default();
+        chain_map.clone_range(0, 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid layer range")]
+    fn clone_range_reversed() {
+        let mut chain_map: ChainMap<&str, i32> = ChainMap::default();
+        chain_map.new_child();
+        chain_map.clone_range(1, 0);
+    }
+
+    #[test]
+    fn for_key() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 1);
+        assert_eq!(chain_map.for_key("x", |v| v.copied().unwrap_or(0)), 1);
+        assert_eq!(chain_map.for_key("y", |v| v.copied().unwrap_or(0)), 0);
+    }
+
+    #[test]
+    fn multi_scope_insert() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.multi_scope_insert(vec![("x", 3), ("y", 3)], 3);
+        assert_eq!(chain_map.depth(), 4);
+        assert_eq!(chain_map.top().len(), 2);
+        assert_eq!(chain_map.get_local("x"), Some(&3));
+        assert!(chain_map[1].is_empty() && chain_map[2].is_empty());
+        chain_map.multi_scope_insert(vec![("z", 3)], 0);
+        assert_eq!(chain_map.depth(), 4);
+        assert_eq!(chain_map.get_local("z"), Some(&3));
+    }
+
+    #[test]
+    fn effective_layer_for() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.insert("y", 0);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        chain_map.insert("z", 1);
+        chain_map.new_child();
+        let layer = chain_map.effective_layer_for("x").unwrap();
+        assert_eq!(layer.get("z"), Some(&1));
+        assert!(chain_map
+            .effective_layer_for("y")
+            .unwrap()
+            .contains_key("y"));
+        assert!(chain_map.effective_layer_for("w").is_none());
     }
 }
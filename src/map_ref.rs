@@ -0,0 +1,547 @@
+use std::{
+    borrow::Borrow,
+    collections::{HashMap, HashSet},
+    hash::Hash,
+};
+
+/// A borrowed view into a contiguous range of a `ChainMap`'s layers.
+///
+/// Lookups behave exactly as they would on a `ChainMap` made up of only
+/// those layers, with the last layer in the range being the innermost.
+pub struct ChainMapRef<'a, K, V> {
+    pub(crate) maps: &'a [HashMap<K, V>],
+}
+
+impl<'a, K: Hash + Eq, V> ChainMapRef<'a, K, V> {
+    /// Returns the value corresponding to the supplied key.
+    ///
+    /// The supplied key may be any borrowed form of the map's key type, but
+    /// `Hash` and `Eq` on the borrowed form *must* match those for
+    /// the key type.
+    pub fn get<Q>(&self, key: &Q) -> Option<&'a V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.maps.iter().rev().find_map(|map| map.get(key))
+    }
+
+    /// Returns true if any layer in the view contains the key.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.maps.iter().any(|map| map.contains_key(key))
+    }
+
+    /// An iterator visiting all visible key-value pairs, starting with
+    /// the innermost layer. Shadowed entries are skipped, so each key
+    /// is yielded exactly once.
+    pub fn iter(&self) -> impl Iterator<Item = (&'a K, &'a V)> {
+        let view = *self;
+        (0..self.maps.len())
+            .rev()
+            .flat_map(move |i| view.visible_in(i))
+    }
+
+    /// Returns the number of visible keys in the view.
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    /// Returns true if no layer of the view contains any entries.
+    pub fn is_empty(&self) -> bool {
+        self.maps.iter().all(HashMap::is_empty)
+    }
+
+    /// An alias for `len`.
+    pub fn count(&self) -> usize {
+        self.len()
+    }
+
+    /// Returns the number of visible entries for which `f` returns true.
+    pub fn count_if<F>(&self, f: F) -> usize
+    where
+        F: Fn(&K, &V) -> bool,
+    {
+        self.iter().filter(|(k, v)| f(k, v)).count()
+    }
+
+    /// Returns the number of layers in the view.
+    pub fn depth(&self) -> usize {
+        self.maps.len()
+    }
+
+    /// Returns the total number of entries stored across every layer of
+    /// the view, counting a shadowed key once for each layer it is in.
+    pub fn chain_len(&self) -> usize {
+        self.maps.iter().map(HashMap::len).sum()
+    }
+
+    /// Returns the number of entries in the top layer of the view.
+    pub fn local_len(&self) -> usize {
+        self.top().len()
+    }
+
+    /// Returns true if the top layer of the view has no entries.
+    pub fn local_is_empty(&self) -> bool {
+        self.top().is_empty()
+    }
+
+    /// An iterator visiting every entry in every layer, including shadowed
+    /// ones, along with the index of the layer it is stored in. Layers are
+    /// visited from the base (index 0) to the top.
+    pub fn iter_with_depth(&self) -> impl Iterator<Item = (usize, &'a K, &'a V)> {
+        self.maps
+            .iter()
+            .enumerate()
+            .flat_map(|(i, map)| map.iter().map(move |(k, v)| (i, k, v)))
+    }
+
+    /// Returns the first visible entry for which `f` returns true,
+    /// searching in the same order as `iter`.
+    pub fn find<F>(&self, f: F) -> Option<(&'a K, &'a V)>
+    where
+        F: Fn(&K, &V) -> bool,
+    {
+        self.iter().find(|(k, v)| f(k, v))
+    }
+
+    /// Returns the first visible entry for which `f` returns true,
+    /// searching the layers from the base to the top.
+    pub fn rfind<F>(&self, f: F) -> Option<(&'a K, &'a V)>
+    where
+        F: Fn(&K, &V) -> bool,
+    {
+        (0..self.maps.len())
+            .flat_map(|i| self.visible_in(i))
+            .find(|(k, v)| f(k, v))
+    }
+
+    /// An iterator visiting all visible values, in the same order as
+    /// `iter`.
+    pub fn values(&self) -> impl Iterator<Item = &'a V> {
+        self.iter().map(|(_, v)| v)
+    }
+
+    /// Collects the visible entries into a map of references into the
+    /// view.
+    pub fn collect_visible(&self) -> HashMap<&'a K, &'a V> {
+        self.iter().collect()
+    }
+
+    /// Collects the keys of every layer into a set of references into the
+    /// view.
+    pub fn collect_keys(&self) -> HashSet<&'a K> {
+        self.maps.iter().flat_map(HashMap::keys).collect()
+    }
+
+    /// An iterator visiting every key that appears in more than one layer
+    /// of the view. Each key is yielded once.
+    pub fn shadowed_keys(&self) -> impl Iterator<Item = &'a K> {
+        let view = *self;
+        (0..self.maps.len()).rev().flat_map(move |i| {
+            let below = &view.maps[..i];
+            view.visible_in(i)
+                .filter(move |(k, _)| below.iter().any(|map| map.contains_key(*k)))
+                .map(|(k, _)| k)
+        })
+    }
+
+    /// Returns true if no key appears in more than one layer of the view.
+    pub fn is_shadow_free(&self) -> bool {
+        self.shadowed_keys().next().is_none()
+    }
+
+    /// An iterator visiting every entry hidden by a definition of the same
+    /// key in a layer above it, as `(key, value, layer_index)`. Entries are
+    /// yielded starting with the innermost layer.
+    pub fn iter_shadowed_pairs(&self) -> impl Iterator<Item = (&'a K, &'a V, usize)> {
+        let maps = self.maps;
+        (0..maps.len()).rev().flat_map(move |i| {
+            let above = &maps[i + 1..];
+            maps[i]
+                .iter()
+                .filter(move |(k, _)| above.iter().any(|map| map.contains_key(*k)))
+                .map(move |(k, v)| (k, v, i))
+        })
+    }
+
+    /// An iterator visiting each key once, like `iter`, but with lookup
+    /// priority reversed: a key defined in several layers is yielded with
+    /// the value from its outermost layer. Entries are yielded starting
+    /// with the base.
+    pub fn iter_visible_in_reverse(&self) -> impl Iterator<Item = (&'a K, &'a V)> {
+        let maps = self.maps;
+        maps.iter().enumerate().flat_map(move |(i, map)| {
+            let below = &maps[..i];
+            map.iter()
+                .filter(move |(k, _)| !below.iter().any(|map| map.contains_key(*k)))
+        })
+    }
+
+    /// An iterator visiting the keys of the layer at `depth` that do not
+    /// appear in any other layer of the view.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `depth` is out of bounds.
+    pub fn keys_only_in(&self, depth: usize) -> impl Iterator<Item = &'a K> {
+        let maps = self.maps;
+        maps[depth].keys().filter(move |k| {
+            maps.iter()
+                .enumerate()
+                .all(|(i, map)| i == depth || !map.contains_key(*k))
+        })
+    }
+
+    /// An iterator visiting every key in the top layer that is not defined
+    /// in any other layer of the view.
+    pub fn keys_added_in_top(&self) -> impl Iterator<Item = &'a K> {
+        let (top, parents) = self.maps.split_last().expect("chain map has no layers");
+        top.keys()
+            .filter(move |k| !parents.iter().any(|map| map.contains_key(*k)))
+    }
+
+    /// An iterator visiting every key in the top layer that is also defined
+    /// in at least one other layer of the view.
+    pub fn iter_local_keys_shadowing_parent(&self) -> impl Iterator<Item = &'a K> {
+        let (top, parents) = self.maps.split_last().expect("chain map has no layers");
+        top.keys()
+            .filter(move |k| parents.iter().any(|map| map.contains_key(*k)))
+    }
+
+    /// Returns the innermost value for `key` for which `f` returns true,
+    /// skipping over any values in inner layers that do not match.
+    pub fn first_match<Q, F>(&self, key: &Q, f: F) -> Option<&'a V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        F: Fn(&V) -> bool,
+    {
+        self.maps
+            .iter()
+            .rev()
+            .filter_map(|map| map.get(key))
+            .find(|v| f(v))
+    }
+
+    /// Calls `f` with the layer index and value of every occurrence of
+    /// `key`, starting with the innermost layer, and returns the first
+    /// `Some` it produces.
+    pub fn depth_search<Q, F, R>(&self, key: &Q, f: F) -> Option<R>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+        F: Fn(usize, &V) -> Option<R>,
+    {
+        self.maps
+            .iter()
+            .enumerate()
+            .rev()
+            .filter_map(|(i, map)| map.get(key).map(|v| (i, v)))
+            .find_map(|(i, v)| f(i, v))
+    }
+
+    /// Returns every value stored for `key` along with the index of the
+    /// layer it is stored in, starting with the innermost layer.
+    pub fn trace_get<Q>(&self, key: &Q) -> Vec<(usize, &'a V)>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.maps
+            .iter()
+            .enumerate()
+            .rev()
+            .filter_map(|(i, map)| map.get(key).map(|v| (i, v)))
+            .collect()
+    }
+
+    /// Returns true if every layer of the view contains the key.
+    pub fn all_layers_contain<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.maps.iter().all(|map| map.contains_key(key))
+    }
+
+    /// Returns true if no layer of the view contains the key. This is the
+    /// same as `!self.contains_key(key)`.
+    pub fn no_layer_contains<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        !self.contains_key(key)
+    }
+
+    /// Returns true if exactly one layer of the view contains the key.
+    pub fn exactly_one_layer_contains<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.num_layers_with_key(key) == 1
+    }
+
+    /// Returns the number of layers that contain `key`.
+    pub fn num_layers_with_key<Q>(&self, key: &Q) -> usize
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.maps.iter().filter(|map| map.contains_key(key)).count()
+    }
+
+    /// Returns the number of times `key` is shadowed: one less than the
+    /// number of layers containing it, or 0 if no layer contains it.
+    pub fn shadow_depth<Q>(&self, key: &Q) -> usize
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.num_layers_with_key(key).saturating_sub(1)
+    }
+
+    /// Returns the index of the innermost layer containing `key`.
+    pub fn highest_layer_with<Q>(&self, key: &Q) -> Option<usize>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.maps.iter().rposition(|map| map.contains_key(key))
+    }
+
+    /// Returns the index of the outermost layer containing `key`.
+    pub fn lowest_layer_with<Q>(&self, key: &Q) -> Option<usize>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.maps.iter().position(|map| map.contains_key(key))
+    }
+
+    /// Returns the index of every layer containing `key`, starting with the
+    /// innermost layer.
+    pub fn position_all<Q>(&self, key: &Q) -> Vec<usize>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.maps
+            .iter()
+            .enumerate()
+            .rev()
+            .filter(|(_, map)| map.contains_key(key))
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// Returns the innermost layer containing `key`, i.e. the layer `get`
+    /// would take its value from.
+    pub fn effective_layer_for<Q>(&self, key: &Q) -> Option<&'a HashMap<K, V>>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.maps.iter().rev().find(|map| map.contains_key(key))
+    }
+
+    /// Returns the top layer of the view.
+    pub fn top(&self) -> &'a HashMap<K, V> {
+        self.maps.last().expect("chain map has no layers")
+    }
+
+    /// Returns the base layer of the view.
+    pub fn base(&self) -> &'a HashMap<K, V> {
+        &self.maps[0]
+    }
+
+    /// Returns the layer directly beneath the top layer, or `None` if the
+    /// view only has one layer.
+    pub fn peek_parent(&self) -> Option<&'a HashMap<K, V>> {
+        let index = self.maps.len().checked_sub(2)?;
+        self.maps.get(index)
+    }
+
+    /// Returns the value for `key` in the top layer, ignoring every other
+    /// layer of the view.
+    pub fn get_local<Q>(&self, key: &Q) -> Option<&'a V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.top().get(key)
+    }
+
+    /// Returns the value for `key` in the base layer, ignoring every layer
+    /// above it.
+    pub fn get_base<Q>(&self, key: &Q) -> Option<&'a V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.base().get(key)
+    }
+
+    /// Returns true if the top layer contains the key, ignoring every other
+    /// layer of the view.
+    pub fn contains_key_local<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.top().contains_key(key)
+    }
+
+    /// Returns true if `key` is defined in the top layer, whether or not
+    /// another layer also defines it.
+    pub fn is_local<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.contains_key_local(key)
+    }
+
+    /// Returns true if `key` is visible in the view only through a layer
+    /// below the top, i.e. it is not defined in the top layer.
+    pub fn is_inherited<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let (top, parents) = self.maps.split_last().expect("chain map has no layers");
+        !top.contains_key(key) && parents.iter().any(|map| map.contains_key(key))
+    }
+
+    /// An iterator visiting every layer, starting with the base.
+    pub fn layer_iter(&self) -> impl Iterator<Item = &'a HashMap<K, V>> {
+        self.maps.iter()
+    }
+
+    /// An iterator visiting every layer, starting with the top.
+    pub fn rev_layer_iter(&self) -> impl Iterator<Item = &'a HashMap<K, V>> {
+        self.maps.iter().rev()
+    }
+
+    /// An iterator visiting every layer along with its index, starting with
+    /// the base.
+    pub fn enumerate_layers(&self) -> impl Iterator<Item = (usize, &'a HashMap<K, V>)> {
+        self.maps.iter().enumerate()
+    }
+
+    /// An iterator visiting every layer except the base, starting with the
+    /// layer directly above it.
+    pub fn iter_child_layers(&self) -> impl Iterator<Item = &'a HashMap<K, V>> {
+        self.maps.iter().skip(1)
+    }
+
+    /// The entries of the layer at `index` that are not shadowed by any
+    /// layer above it.
+    pub(crate) fn visible_in(&self, index: usize) -> impl Iterator<Item = (&'a K, &'a V)> {
+        let above = &self.maps[index + 1..];
+        self.maps[index]
+            .iter()
+            .filter(move |(k, _)| !above.iter().any(|map| map.contains_key(*k)))
+    }
+}
+
+impl<'a, K, V> Clone for ChainMapRef<'a, K, V> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a, K, V> Copy for ChainMapRef<'a, K, V> {}
+
+#[cfg(test)]
+mod test {
+    use crate::ChainMap;
+
+    #[test]
+    fn get() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        chain_map.insert("y", 2);
+        let view = chain_map.view();
+        assert_eq!(view.get("x"), Some(&1));
+        assert_eq!(view.get("y"), Some(&2));
+        assert_eq!(view.get("z"), None);
+    }
+
+    #[test]
+    fn sub_chain() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        chain_map.insert("y", 1);
+        chain_map.new_child();
+        chain_map.insert("z", 2);
+        let view = chain_map.sub_chain(0, 1);
+        assert_eq!(view.depth(), 2);
+        assert_eq!(view.get("x"), Some(&1));
+        assert!(!view.contains_key("z"));
+        assert_eq!(view.len(), 2);
+        let base = chain_map.sub_chain(0, 0);
+        assert_eq!(base.get("x"), Some(&0));
+        assert_eq!(base.len(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid layer range")]
+    fn sub_chain_reversed() {
+        let mut chain_map: ChainMap<&str, i32> = ChainMap::default();
+        chain_map.new_child();
+        chain_map.sub_chain(1, 0);
+    }
+
+    #[test]
+    fn iter() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        let pairs: Vec<_> = chain_map.view().iter().collect();
+        assert_eq!(pairs, vec![(&"x", &1)]);
+    }
+
+    #[test]
+    fn sub_chain_lookups() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        chain_map.new_child();
+        chain_map.insert("x", 1);
+        chain_map.insert("y", 1);
+        chain_map.new_child();
+        chain_map.insert("y", 2);
+        let view = chain_map.sub_chain(0, 1);
+        assert_eq!(view.top().len(), 2);
+        assert_eq!(view.get_local("x"), Some(&1));
+        assert_eq!(view.get_base("x"), Some(&0));
+        assert!(!view.is_inherited("x"));
+        assert_eq!(view.highest_layer_with("y"), Some(1));
+        assert_eq!(view.trace_get("x"), vec![(1, &1), (0, &0)]);
+        assert_eq!(view.shadowed_keys().collect::<Vec<_>>(), vec![&"x"]);
+        assert_eq!(view.chain_len(), 3);
+        let top = chain_map.subtree(2);
+        assert_eq!(top.get("y"), Some(&2));
+        assert!(top.is_shadow_free());
+    }
+
+    #[test]
+    fn outlives_view() {
+        let mut chain_map = ChainMap::default();
+        chain_map.insert("x", 0);
+        let value = chain_map.view().get("x");
+        let pairs: Vec<_> = chain_map.view().iter().collect();
+        assert_eq!(value, Some(&0));
+        assert_eq!(pairs, vec![(&"x", &0)]);
+    }
+}
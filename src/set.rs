@@ -1,4 +1,4 @@
-use std::{borrow::Borrow, collections::HashSet, hash::Hash, mem::replace};
+use std::{borrow::Borrow, collections::HashSet, hash::Hash, mem::take};
 
 pub struct ChainSet<T> {
     pub(crate) sets: Vec<HashSet<T>>,
@@ -20,10 +20,10 @@ impl<T: Hash + Eq> ChainSet<T> {
         }
     }
 
-    pub fn get<Q: ?Sized>(&self, value: &Q) -> Option<&T>
+    pub fn get<Q>(&self, value: &Q) -> Option<&T>
     where
         T: Borrow<Q>,
-        Q: Hash + Eq,
+        Q: Hash + Eq + ?Sized,
     {
         for set in self.sets.iter().rev() {
             if let Some(v) = set.get(value) {
@@ -43,7 +43,7 @@ impl<T: Hash + Eq> ChainSet<T> {
 
     pub fn remove_child(&mut self) -> Option<HashSet<T>> {
         if self.sets.len() == 1 {
-            let ret = replace(&mut self.sets[0], HashSet::new());
+            let ret = take(&mut self.sets[0]);
             Some(ret)
         } else {
             self.sets.pop()
@@ -70,7 +70,7 @@ mod test {
         test_set.insert("test");
         let chain_set = ChainSet::new(test_set);
 
-        assert!(chain_set.sets.len() > 0);
+        assert!(!chain_set.sets.is_empty());
         assert_eq!(chain_set.sets[0].get("test"), Some(&"test"));
     }
 
@@ -78,7 +78,7 @@ mod test {
     fn initialization_default() {
         let chain_set: ChainSet<()> = ChainSet::default();
 
-        assert!(chain_set.sets.len() > 0);
+        assert!(!chain_set.sets.is_empty());
         assert!(chain_set.sets[0].is_empty());
     }
 